@@ -0,0 +1,441 @@
+//! Static control flow analysis of a ROM.
+//!
+//! Starting at `ROM_START_ADDR`, `analyze` follows jumps, calls, returns and
+//! skips to find every reachable instruction without running the program. The
+//! result is split into basic blocks and subroutines, and the ROM bytes no
+//! reachable instruction covers are reported as probable data. Computed jumps
+//! (`BNNN`) cannot be followed statically and are listed as unresolved.
+
+use crate::chip8::{LONG_LOAD_OPCODE, MEMORY_SIZE, Opcode, ROM_START_ADDR};
+use crate::disasm::{self, Instruction};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::ops::Range;
+
+/// How control gets from one instruction or block to another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    FallThrough,
+    Jump,
+    Call,
+    /// The path taken when a skip instruction skips.
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+/// A straight run of instructions that is only entered at the top and only left at the bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// The addresses of the block's instructions, in order.
+    pub instructions: Vec<usize>,
+    pub successors: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subroutine {
+    pub entry: usize,
+    /// The addresses of the `2NNN` instructions that call it, empty for the program entry.
+    pub callers: Vec<usize>,
+    /// The start addresses of the blocks reachable from the entry without following calls.
+    pub blocks: BTreeSet<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    rom: Vec<u8>,
+    /// Every reachable instruction by address.
+    pub instructions: BTreeMap<usize, Instruction>,
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    pub subroutines: BTreeMap<usize, Subroutine>,
+    /// Addresses of `BNNN` instructions, whose targets depend on a register.
+    pub unresolved: Vec<usize>,
+    /// Addresses of reachable bytes that are not a valid instruction.
+    pub invalid: Vec<usize>,
+    /// Jumps and calls (from, to) that leave the ROM.
+    pub outside_targets: Vec<(usize, usize)>,
+    /// Targets of `ANNN` and `F000 NNNN`, which usually point at sprites or other data.
+    pub data_references: BTreeSet<usize>,
+    /// ROM ranges no reachable instruction covers.
+    pub data_regions: Vec<Range<usize>>,
+}
+
+/// What an instruction does to the program counter.
+enum Flow {
+    Next,
+    Jump(usize),
+    Call(usize),
+    Skip,
+    Return,
+    /// Exit, an invalid instruction or a computed jump: no known successor.
+    Stop,
+}
+
+fn flow_of(instruction: &Instruction) -> Flow {
+    if instruction.len() < 2 {
+        return Flow::Stop;
+    }
+    let opcode = Opcode::new(u16::from_be_bytes([
+        instruction.bytes[0],
+        instruction.bytes[1],
+    ]));
+    let is_data = instruction.text.starts_with("db ");
+    match opcode.op_type() {
+        _ if is_data => Flow::Stop,
+        0x0 if opcode.get_nnn() == 0x0EE => Flow::Return,
+        0x0 if opcode.get_nnn() == 0x0FD => Flow::Stop,
+        0x1 => Flow::Jump(opcode.get_nnn() as usize),
+        0x2 => Flow::Call(opcode.get_nnn() as usize),
+        0x3 | 0x4 | 0x9 | 0xE => Flow::Skip,
+        0x5 if opcode.n() == 0 => Flow::Skip,
+        0xB => Flow::Stop,
+        _ => Flow::Next,
+    }
+}
+
+/// Analyzes `rom` as loaded at `ROM_START_ADDR`.
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let rom_range = ROM_START_ADDR..ROM_START_ADDR + rom.len();
+    let mut memory = vec![0; MEMORY_SIZE];
+    let rom_len = rom.len().min(MEMORY_SIZE - ROM_START_ADDR);
+    memory[ROM_START_ADDR..ROM_START_ADDR + rom_len].copy_from_slice(&rom[..rom_len]);
+
+    let mut analysis = Analysis {
+        rom: rom.to_vec(),
+        instructions: BTreeMap::new(),
+        blocks: BTreeMap::new(),
+        subroutines: BTreeMap::new(),
+        unresolved: Vec::new(),
+        invalid: Vec::new(),
+        outside_targets: Vec::new(),
+        data_references: BTreeSet::new(),
+        data_regions: Vec::new(),
+    };
+
+    // Follow every path from the entry point, remembering each instruction's successors.
+    let mut successors: BTreeMap<usize, Vec<Edge>> = BTreeMap::new();
+    let mut leaders = BTreeSet::from([ROM_START_ADDR]);
+    let mut callers: BTreeMap<usize, Vec<usize>> = BTreeMap::from([(ROM_START_ADDR, Vec::new())]);
+    let mut worklist = vec![ROM_START_ADDR];
+
+    while let Some(address) = worklist.pop() {
+        if analysis.instructions.contains_key(&address) {
+            continue;
+        }
+        let instruction = disasm::disassemble_at(&memory, address);
+        let next = address + instruction.len();
+        let edges = match flow_of(&instruction) {
+            Flow::Next => vec![Edge {
+                target: next,
+                kind: EdgeKind::FallThrough,
+            }],
+            Flow::Jump(target) => vec![Edge {
+                target,
+                kind: EdgeKind::Jump,
+            }],
+            Flow::Call(target) => {
+                callers.entry(target).or_default().push(address);
+                vec![
+                    Edge {
+                        target,
+                        kind: EdgeKind::Call,
+                    },
+                    Edge {
+                        target: next,
+                        kind: EdgeKind::FallThrough,
+                    },
+                ]
+            }
+            Flow::Skip => {
+                let skipped = disasm::disassemble_at(&memory, next);
+                vec![
+                    Edge {
+                        target: next,
+                        kind: EdgeKind::FallThrough,
+                    },
+                    Edge {
+                        target: next + skipped.len(),
+                        kind: EdgeKind::Skip,
+                    },
+                ]
+            }
+            Flow::Return | Flow::Stop => Vec::new(),
+        };
+
+        let opcode = Opcode::new(u16::from_be_bytes([
+            memory[address],
+            memory[(address + 1) % MEMORY_SIZE],
+        ]));
+        if instruction.text.starts_with("db ") {
+            analysis.invalid.push(address);
+        } else if opcode.op_type() == 0xB {
+            analysis.unresolved.push(address);
+        } else if opcode.op_type() == 0xA {
+            analysis.data_references.insert(opcode.get_nnn() as usize);
+        } else if opcode.raw() == LONG_LOAD_OPCODE && instruction.len() == 4 {
+            let target = u16::from_be_bytes([instruction.bytes[2], instruction.bytes[3]]);
+            analysis.data_references.insert(target as usize);
+        }
+
+        let ends_block = !matches!(
+            edges.as_slice(),
+            [Edge {
+                kind: EdgeKind::FallThrough,
+                ..
+            }]
+        );
+        for edge in &edges {
+            if !rom_range.contains(&edge.target) {
+                analysis.outside_targets.push((address, edge.target));
+                continue;
+            }
+            if ends_block {
+                leaders.insert(edge.target);
+            }
+            worklist.push(edge.target);
+        }
+        successors.insert(address, edges);
+        analysis.instructions.insert(address, instruction);
+    }
+
+    // Cut the reachable instructions into blocks at every leader and after every branch.
+    for &leader in &leaders {
+        if !analysis.instructions.contains_key(&leader) {
+            continue;
+        }
+        let mut block = BasicBlock {
+            start: leader,
+            instructions: Vec::new(),
+            successors: Vec::new(),
+        };
+        let mut address = leader;
+        loop {
+            block.instructions.push(address);
+            let edges = &successors[&address];
+            match edges.as_slice() {
+                [
+                    edge @ Edge {
+                        kind: EdgeKind::FallThrough,
+                        ..
+                    },
+                ] if !leaders.contains(&edge.target)
+                    && analysis.instructions.contains_key(&edge.target) =>
+                {
+                    address = edge.target;
+                }
+                _ => {
+                    block.successors = edges.clone();
+                    break;
+                }
+            }
+        }
+        analysis.blocks.insert(leader, block);
+    }
+
+    // A subroutine is everything reachable from its entry without following calls.
+    for (entry, callers) in callers {
+        if !analysis.blocks.contains_key(&entry) {
+            continue;
+        }
+        let mut blocks = BTreeSet::new();
+        let mut pending = vec![entry];
+        while let Some(start) = pending.pop() {
+            let Some(block) = analysis.blocks.get(&start) else {
+                continue;
+            };
+            if !blocks.insert(start) {
+                continue;
+            }
+            pending.extend(
+                block
+                    .successors
+                    .iter()
+                    .filter(|edge| edge.kind != EdgeKind::Call)
+                    .map(|edge| edge.target),
+            );
+        }
+        analysis.subroutines.insert(
+            entry,
+            Subroutine {
+                entry,
+                callers,
+                blocks,
+            },
+        );
+    }
+
+    // Whatever no reachable instruction covers is probably data.
+    let mut covered = vec![false; rom.len()];
+    for instruction in analysis.instructions.values() {
+        for address in instruction.address..instruction.address + instruction.len() {
+            if let Some(byte) = covered.get_mut(address.wrapping_sub(ROM_START_ADDR)) {
+                *byte = true;
+            }
+        }
+    }
+    let mut offset = 0;
+    while offset < covered.len() {
+        let run = covered[offset..]
+            .iter()
+            .take_while(|&&is_covered| is_covered == covered[offset])
+            .count();
+        if !covered[offset] {
+            let start = ROM_START_ADDR + offset;
+            analysis.data_regions.push(start..start + run);
+        }
+        offset += run;
+    }
+
+    analysis.unresolved.sort();
+    analysis.invalid.sort();
+    analysis.outside_targets.sort();
+    analysis
+}
+
+impl Analysis {
+    fn label(&self, address: usize) -> Option<String> {
+        if address == ROM_START_ADDR {
+            Some("main".to_string())
+        } else if self.subroutines.contains_key(&address) {
+            Some(format!("sub_{:03X}", address))
+        } else if self.blocks.contains_key(&address) {
+            Some(format!("loc_{:03X}", address))
+        } else if self.data_references.contains(&address) {
+            Some(format!("data_{:03X}", address))
+        } else {
+            None
+        }
+    }
+
+    /// The ROM front to back with labels, reachable code as instructions and everything else as data.
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        let code_bytes: usize = self.instructions.values().map(Instruction::len).sum();
+        let data_bytes: usize = self.data_regions.iter().map(|region| region.len()).sum();
+        writeln!(
+            out,
+            "; {} basic blocks, {} subroutines, {} instructions ({} bytes), {} bytes of probable data",
+            self.blocks.len(),
+            self.subroutines.len(),
+            self.instructions.len(),
+            code_bytes,
+            data_bytes
+        )
+        .unwrap();
+        for &address in &self.unresolved {
+            writeln!(out, "; unresolved computed jump at 0x{:03X}", address).unwrap();
+        }
+        for &address in &self.invalid {
+            writeln!(out, "; invalid instruction reached at 0x{:03X}", address).unwrap();
+        }
+        for &(from, to) in &self.outside_targets {
+            writeln!(
+                out,
+                "; 0x{:03X} leads to 0x{:03X}, outside the ROM",
+                from, to
+            )
+            .unwrap();
+        }
+
+        let rom_end = ROM_START_ADDR + self.rom.len();
+        let mut address = ROM_START_ADDR;
+        while address < rom_end {
+            if let Some(label) = self.label(address) {
+                writeln!(out).unwrap();
+                match self.subroutines.get(&address) {
+                    Some(subroutine) if !subroutine.callers.is_empty() => {
+                        let callers: Vec<String> = subroutine
+                            .callers
+                            .iter()
+                            .map(|caller| format!("0x{:03X}", caller))
+                            .collect();
+                        writeln!(out, "{}: ; called from {}", label, callers.join(", ")).unwrap();
+                    }
+                    _ => writeln!(out, "{}:", label).unwrap(),
+                }
+            }
+
+            if let Some(instruction) = self.instructions.get(&address) {
+                let mut line = disasm::format_line(instruction);
+                if self.unresolved.contains(&address) {
+                    line.push_str(" ; unresolved target");
+                }
+                writeln!(out, "{}", line).unwrap();
+                address += instruction.len();
+                continue;
+            }
+
+            // Data runs until the next instruction or label, at most 8 bytes per line.
+            let mut end = address + 1;
+            while end < rom_end
+                && end - address < 8
+                && !self.instructions.contains_key(&end)
+                && self.label(end).is_none()
+            {
+                end += 1;
+            }
+            let bytes = &self.rom[address - ROM_START_ADDR..end - ROM_START_ADDR];
+            let data = Instruction {
+                address,
+                bytes: bytes.to_vec(),
+                text: disasm::data_directive(bytes),
+            };
+            writeln!(out, "{}", disasm::format_line(&data)).unwrap();
+            address = end;
+        }
+        out
+    }
+
+    /// The block graph in Graphviz DOT format. Calls are dashed, skips dotted
+    /// and unresolved jumps point at a `?` node.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph chip8 {{").unwrap();
+        writeln!(out, "    node [shape=box fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = match self.label(block.start) {
+                Some(name) => format!("{}\\l", name),
+                None => String::new(),
+            };
+            for address in &block.instructions {
+                let instruction = &self.instructions[address];
+                label.push_str(&format!("{:03X}  {}\\l", address, instruction.text));
+            }
+            writeln!(out, "    b{:03X} [label=\"{}\"];", block.start, label).unwrap();
+        }
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let style = match edge.kind {
+                    EdgeKind::FallThrough | EdgeKind::Jump => "",
+                    EdgeKind::Call => " [style=dashed]",
+                    EdgeKind::Skip => " [style=dotted label=\"skip\"]",
+                };
+                if self.blocks.contains_key(&edge.target) {
+                    writeln!(
+                        out,
+                        "    b{:03X} -> b{:03X}{};",
+                        block.start, edge.target, style
+                    )
+                    .unwrap();
+                }
+            }
+            let last = block.instructions.last().copied().unwrap_or(block.start);
+            if self.unresolved.contains(&last) {
+                writeln!(
+                    out,
+                    "    unresolved_{:03X} [label=\"?\" shape=circle];",
+                    last
+                )
+                .unwrap();
+                writeln!(out, "    b{:03X} -> unresolved_{:03X};", block.start, last).unwrap();
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
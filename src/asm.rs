@@ -0,0 +1,349 @@
+//! Assembles the syntax `disasm` prints back into a ROM.
+//!
+//! One statement per line, optionally preceded by `label:` and followed by a
+//! `; comment`. Mnemonics and register names are case insensitive, labels are
+//! not. Numbers are decimal, `0x` hex or `0b` binary, and a label can be used
+//! anywhere a number can. `db` emits its operands as bytes, and
+//! `LD I, LONG addr` is the 4 byte XO-CHIP `F000 NNNN` load.
+
+use crate::chip8::{LONG_LOAD_OPCODE, ROM_START_ADDR};
+use std::collections::HashMap;
+
+/// An error in the source, with the 1-based line it was found on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A statement with its labels stripped, placed at `address`.
+struct Statement<'a> {
+    line: usize,
+    address: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Register(u8),
+    I,
+    /// `[I]`, the memory I points at.
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(u32),
+    Value(u32),
+}
+
+/// Assembles `source` into ROM bytes to be loaded at `ROM_START_ADDR`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = ROM_START_ADDR;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |message: String| AssembleError {
+            line: line_number,
+            message,
+        };
+        let mut rest = line.split(';').next().unwrap().trim();
+
+        while let Some((label, after)) = rest.split_once(':') {
+            let label = label.trim();
+            if !is_identifier(label) {
+                return Err(error(format!("Invalid label '{}'", label)));
+            }
+            if labels.insert(label, address).is_some() {
+                return Err(error(format!("Label '{}' is defined twice", label)));
+            }
+            rest = after.trim();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match rest.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (
+                mnemonic.to_ascii_uppercase(),
+                operands.split(',').map(str::trim).collect(),
+            ),
+            None => (rest.to_ascii_uppercase(), Vec::new()),
+        };
+        let statement = Statement {
+            line: line_number,
+            address,
+            mnemonic,
+            operands,
+        };
+        address += statement_size(&statement);
+        statements.push(statement);
+    }
+
+    let mut rom = Vec::new();
+    for statement in &statements {
+        let bytes = encode(statement, &labels).map_err(|message| AssembleError {
+            line: statement.line,
+            message,
+        })?;
+        debug_assert_eq!(ROM_START_ADDR + rom.len(), statement.address);
+        rom.extend(bytes);
+    }
+    Ok(rom)
+}
+
+/// The number of bytes a statement assembles to, known before labels are resolved.
+fn statement_size(statement: &Statement) -> usize {
+    if statement.mnemonic == "DB" {
+        statement.operands.len()
+    } else if statement.mnemonic == "LD"
+        && statement
+            .operands
+            .get(1)
+            .is_some_and(|operand| operand.to_ascii_uppercase().starts_with("LONG "))
+    {
+        4
+    } else {
+        2
+    }
+}
+
+fn encode(statement: &Statement, labels: &HashMap<&str, usize>) -> Result<Vec<u8>, String> {
+    if statement.mnemonic == "DB" {
+        return statement
+            .operands
+            .iter()
+            .map(|operand| {
+                let value = parse_value(operand, labels)?;
+                u8::try_from(value).map_err(|_| format!("{:#X} does not fit in a byte", value))
+            })
+            .collect();
+    }
+
+    let operands = statement
+        .operands
+        .iter()
+        .map(|operand| parse_operand(operand, labels))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    use Operand::*;
+    let opcode: u16 = match (statement.mnemonic.as_str(), operands.as_slice()) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCD", [Value(n)]) => 0x00C0 | nibble(*n)?,
+        ("SCU", [Value(n)]) => 0x00D0 | nibble(*n)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("SYS", [Value(nnn)]) => address(*nnn)?,
+        ("JP", [Value(nnn)]) => 0x1000 | address(*nnn)?,
+        ("JP", [Register(0), Value(nnn)]) => 0xB000 | address(*nnn)?,
+        ("CALL", [Value(nnn)]) => 0x2000 | address(*nnn)?,
+        ("SE", [Register(x), Value(nn)]) => 0x3000 | vx(*x) | byte(*nn)?,
+        ("SE", [Register(x), Register(y)]) => 0x5000 | vx(*x) | vy(*y),
+        ("SNE", [Register(x), Value(nn)]) => 0x4000 | vx(*x) | byte(*nn)?,
+        ("SNE", [Register(x), Register(y)]) => 0x9000 | vx(*x) | vy(*y),
+        ("SAVE", [Register(x), Register(y)]) => 0x5002 | vx(*x) | vy(*y),
+        ("LOAD", [Register(x), Register(y)]) => 0x5003 | vx(*x) | vy(*y),
+        ("LD", [Register(x), Value(nn)]) => 0x6000 | vx(*x) | byte(*nn)?,
+        ("LD", [Register(x), Register(y)]) => 0x8000 | vx(*x) | vy(*y),
+        ("LD", [I, Value(nnn)]) => 0xA000 | address(*nnn)?,
+        ("LD", [I, Long(long_address)]) => {
+            let long_address = u16::try_from(*long_address)
+                .map_err(|_| format!("Address {:#X} does not fit in 16 bits", long_address))?;
+            let mut bytes = LONG_LOAD_OPCODE.to_be_bytes().to_vec();
+            bytes.extend(long_address.to_be_bytes());
+            return Ok(bytes);
+        }
+        ("LD", [Register(x), Dt]) => 0xF007 | vx(*x),
+        ("LD", [Register(x), K]) => 0xF00A | vx(*x),
+        ("LD", [Dt, Register(x)]) => 0xF015 | vx(*x),
+        ("LD", [St, Register(x)]) => 0xF018 | vx(*x),
+        ("LD", [F, Register(x)]) => 0xF029 | vx(*x),
+        ("LD", [Hf, Register(x)]) => 0xF030 | vx(*x),
+        ("LD", [B, Register(x)]) => 0xF033 | vx(*x),
+        ("LD", [IndirectI, Register(x)]) => 0xF055 | vx(*x),
+        ("LD", [Register(x), IndirectI]) => 0xF065 | vx(*x),
+        ("LD", [R, Register(x)]) => 0xF075 | vx(*x),
+        ("LD", [Register(x), R]) => 0xF085 | vx(*x),
+        ("ADD", [Register(x), Value(nn)]) => 0x7000 | vx(*x) | byte(*nn)?,
+        ("ADD", [Register(x), Register(y)]) => 0x8004 | vx(*x) | vy(*y),
+        ("ADD", [I, Register(x)]) => 0xF01E | vx(*x),
+        ("OR", [Register(x), Register(y)]) => 0x8001 | vx(*x) | vy(*y),
+        ("AND", [Register(x), Register(y)]) => 0x8002 | vx(*x) | vy(*y),
+        ("XOR", [Register(x), Register(y)]) => 0x8003 | vx(*x) | vy(*y),
+        ("SUB", [Register(x), Register(y)]) => 0x8005 | vx(*x) | vy(*y),
+        ("SHR", [Register(x), Register(y)]) => 0x8006 | vx(*x) | vy(*y),
+        ("SUBN", [Register(x), Register(y)]) => 0x8007 | vx(*x) | vy(*y),
+        ("SHL", [Register(x), Register(y)]) => 0x800E | vx(*x) | vy(*y),
+        ("RND", [Register(x), Value(nn)]) => 0xC000 | vx(*x) | byte(*nn)?,
+        ("DRW", [Register(x), Register(y), Value(n)]) => 0xD000 | vx(*x) | vy(*y) | nibble(*n)?,
+        ("SKP", [Register(x)]) => 0xE09E | vx(*x),
+        ("SKNP", [Register(x)]) => 0xE0A1 | vx(*x),
+        ("PLANE", [Value(n)]) => 0xF001 | (nibble(*n)? << 8),
+        ("AUDIO", []) => 0xF002,
+        ("PITCH", [Register(x)]) => 0xF03A | vx(*x),
+        _ => {
+            let instruction = format!("{} {}", statement.mnemonic, statement.operands.join(", "));
+            return Err(format!("Unknown instruction '{}'", instruction.trim_end()));
+        }
+    };
+    Ok(opcode.to_be_bytes().to_vec())
+}
+
+fn parse_operand(operand: &str, labels: &HashMap<&str, usize>) -> Result<Operand, String> {
+    let upper = operand.to_ascii_uppercase();
+    let parsed = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::Hf,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            match u8::from_str_radix(&upper[1..], 16) {
+                Ok(register) => Operand::Register(register),
+                Err(_) => Operand::Value(parse_value(operand, labels)?),
+            }
+        }
+        _ if upper.starts_with("LONG ") => Operand::Long(parse_value(operand[5..].trim(), labels)?),
+        _ => Operand::Value(parse_value(operand, labels)?),
+    };
+    Ok(parsed)
+}
+
+fn parse_value(operand: &str, labels: &HashMap<&str, usize>) -> Result<u32, String> {
+    let lower = operand.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2)
+    } else if operand.starts_with(|c: char| c.is_ascii_digit()) {
+        operand.parse()
+    } else {
+        return match labels.get(operand) {
+            Some(&address) => Ok(address as u32),
+            None => Err(format!("Unknown label '{}'", operand)),
+        };
+    };
+    parsed.map_err(|_| format!("Invalid number '{}'", operand))
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn vx(x: u8) -> u16 {
+    (x as u16) << 8
+}
+
+fn vy(y: u8) -> u16 {
+    (y as u16) << 4
+}
+
+fn nibble(value: u32) -> Result<u16, String> {
+    if value > 0xF {
+        return Err(format!("{:#X} does not fit in 4 bits", value));
+    }
+    Ok(value as u16)
+}
+
+fn byte(value: u32) -> Result<u16, String> {
+    if value > 0xFF {
+        return Err(format!("{:#X} does not fit in a byte", value));
+    }
+    Ok(value as u16)
+}
+
+fn address(value: u32) -> Result<u16, String> {
+    if value > 0xFFF {
+        return Err(format!("Address {:#X} does not fit in 12 bits", value));
+    }
+    Ok(value as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+
+    /// Disassembles `rom` and assembles the listing text back.
+    fn round_trip(rom: &[u8]) -> Vec<u8> {
+        let source: Vec<String> = disasm::disassemble_rom(rom)
+            .into_iter()
+            .map(|instruction| instruction.text)
+            .collect();
+        assemble(&source.join("\n")).unwrap()
+    }
+
+    #[test]
+    fn every_word_reassembles_to_itself() {
+        for raw in 0..=u16::MAX {
+            let mut rom = raw.to_be_bytes().to_vec();
+            if raw == LONG_LOAD_OPCODE {
+                rom.extend([0x12, 0x34]);
+            }
+            assert_eq!(round_trip(&rom), rom, "{:04X}", raw);
+        }
+    }
+
+    #[test]
+    fn trailing_odd_byte_reassembles_to_itself() {
+        assert_eq!(round_trip(&[0x00, 0xE0, 0xAB]), [0x00, 0xE0, 0xAB]);
+    }
+
+    #[test]
+    fn labels_resolve_forward_and_backward() {
+        let source = "start: CLS\n\
+                      JP end ; forward\n\
+                      loop:\n\
+                      CALL start\n\
+                      end: JP loop";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x00, 0xE0, 0x12, 0x06, 0x22, 0x00, 0x12, 0x04]
+        );
+    }
+
+    #[test]
+    fn undefined_and_duplicate_labels_are_errors() {
+        let error = assemble("CLS\nJP nowhere").unwrap_err();
+        assert_eq!(error.line, 2);
+        let error = assemble("here: CLS\nhere: CLS").unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn db_emits_bytes_and_moves_labels() {
+        let source = "db 1, 0x2F, 0b101\nafter: JP after";
+        assert_eq!(assemble(source).unwrap(), [0x01, 0x2F, 0x05, 0x12, 0x03]);
+        assert!(assemble("db 0x100").is_err());
+    }
+
+    #[test]
+    fn long_load_takes_four_bytes() {
+        let source = "LD I, LONG data\nLD I, long 0xABCD\ndata: db 0xFF";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0xF0, 0x00, 0x02, 0x08, 0xF0, 0x00, 0xAB, 0xCD, 0xFF]
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use crate::chip8::AUDIO_PATTERN_SIZE;
+use crate::framebuffer::Framebuffer;
+
+pub const NUM_KEYS: usize = 16;
+
+/// The display side of a backend: presents the machine's framebuffer.
+pub trait DisplayBackend {
+    /// Converts `framebuffer` to colors and shows it.
+    fn render_frame(&mut self, framebuffer: &Framebuffer);
+}
+
+/// Emulator commands bound to keys outside the CHIP-8 keypad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hotkey {
+    /// Save the machine state to the numbered slot.
+    SaveState(u8),
+    /// Load the machine state from the numbered slot.
+    LoadState(u8),
+    /// Step back one frame. Reported on every poll while its key is held.
+    Rewind,
+}
+
+/// The input side of a backend: the 16 key hex keypad and emulator hotkeys.
+pub trait InputBackend {
+    /// Processes pending input events. Returns false once the user asked to quit.
+    fn poll_input(&mut self) -> bool;
+
+    /// Only the low nibble of `key_num` is used, like the VIP did for `EX9E` and `EXA1` with VX above 0xF.
+    fn is_key_pressed(&self, key_num: u8) -> bool;
+
+    /// Overrides the state of a key until the next input event changes it.
+    fn set_key(&mut self, key_num: u8, pressed: bool);
+
+    /// Hotkeys pressed since the last call, oldest first.
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        Vec::new()
+    }
+}
+
+/// The audio side of a backend: a tone driven by the sound timer.
+pub trait AudioBackend {
+    fn play_audio(&self);
+
+    fn pause_audio(&self);
+
+    /// Switches from the default tone to an XO-CHIP 1-bit audio pattern, played
+    /// most significant bit first and looped at 4000 * 2^((pitch - 64) / 48) bits per second.
+    fn set_audio_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], pitch: u8);
+}
+
+/// Everything `Chip8` needs from its environment.
+/// Implemented automatically for any type providing display, input and audio.
+pub trait Backend: DisplayBackend + InputBackend + AudioBackend {}
+
+impl<T: DisplayBackend + InputBackend + AudioBackend> Backend for T {}
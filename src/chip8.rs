@@ -1,34 +1,210 @@
-use crate::chip8_io;
-use rand::distr::{Distribution, Uniform};
+use crate::backend::{Backend, NUM_KEYS};
+use crate::framebuffer::{Framebuffer, NUM_PLANES};
+use crate::machine_code::{MachineCodePolicy, SkippedCall};
+use crate::quirks::{LoadStoreQuirk, Quirks};
+use crate::savestate::{self, SaveStateError, StateReader, StateWriter};
+use crate::smc::{CodeEvent, CodeTracker};
+use std::collections::HashSet;
 use std::io::{Read, Seek, SeekFrom};
 use std::{cell::RefCell, rc::Rc};
 
-const MEMORY_SIZE: usize = 0x1000;
-const ROM_START_ADDR: usize = 0x200;
-const FONT_START_ADDR: usize = 0x50;
-const NUM_REGISTERS: usize = 0x10;
+/// XO-CHIP's 64KB address space. Classic programs only use the first 4KB.
+pub const MEMORY_SIZE: usize = 0x10000;
+pub const ROM_START_ADDR: usize = 0x200;
+pub const FONT_START_ADDR: usize = 0x50;
+pub const NUM_REGISTERS: usize = 0x10;
+pub const FONT_SIZE: usize = 80;
+pub const FONT: [u8; FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+pub const BIG_FONT_START_ADDR: usize = FONT_START_ADDR + FONT_SIZE;
+pub const BIG_FONT_SIZE: usize = 160;
+/// The XO-CHIP instruction that loads a 16 bit address into I, followed by the address itself.
+pub(crate) const LONG_LOAD_OPCODE: u16 = 0xF000;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+/// The XO-CHIP pitch register value at which the audio pattern plays at 4000 bits per second.
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// SUPER-CHIP's 8x10 digits, extended with A-F.
+pub const BIG_FONT: [u8; BIG_FONT_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
 
 #[derive(Debug)]
 pub enum Chip8Error {
-    InvaidOpcode(u16),
+    InvalidOpcode(u16),
     StackUnderflow(Opcode),
+    /// `2NNN` was executed with `Quirks::stack_depth` return addresses already on the stack.
+    StackOverflow(Opcode),
+    /// The ROM (of the given size in bytes) does not fit between `ROM_START_ADDR` and the end of memory.
+    RomTooLarge(usize),
+    /// The ROM file could not be read.
+    RomRead(std::io::Error),
+    /// `load_font` was asked for `size` bytes from a buffer of `buffer_len` bytes,
+    /// or for more than fit between `FONT_START_ADDR` and `ROM_START_ADDR`.
+    InvalidFontSize {
+        size: usize,
+        buffer_len: usize,
+    },
+    /// The instruction at `pc` accessed `address`, past the end of memory, under `OutOfBoundsPolicy::Error`.
+    MemoryOutOfBounds {
+        address: usize,
+        pc: usize,
+        opcode: u16,
+    },
+    /// The program counter ran past the end of memory under `OutOfBoundsPolicy::Error`.
+    PcOutOfBounds(usize),
 }
 
 impl std::fmt::Display for Chip8Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Chip8Error::InvaidOpcode(opcode) => {
+            Chip8Error::InvalidOpcode(opcode) => {
                 write!(f, "Unknown opcode error: {}", opcode)
             }
             Chip8Error::StackUnderflow(opcode) => {
                 write!(f, "StackUnderflow error: opcode: {:#?}", opcode)
             }
+            Chip8Error::StackOverflow(opcode) => {
+                write!(f, "StackOverflow error: opcode: {:#?}", opcode)
+            }
+            Chip8Error::RomTooLarge(size) => {
+                write!(
+                    f,
+                    "RomTooLarge error: {} bytes, at most {} fit in memory",
+                    size,
+                    MEMORY_SIZE - ROM_START_ADDR
+                )
+            }
+            Chip8Error::RomRead(e) => write!(f, "RomRead error: {}", e),
+            Chip8Error::InvalidFontSize { size, buffer_len } => {
+                write!(
+                    f,
+                    "InvalidFontSize error: {} bytes from a {} byte buffer, at most {} fit before the ROM",
+                    size,
+                    buffer_len,
+                    ROM_START_ADDR - FONT_START_ADDR
+                )
+            }
+            Chip8Error::MemoryOutOfBounds {
+                address,
+                pc,
+                opcode,
+            } => {
+                write!(
+                    f,
+                    "MemoryOutOfBounds error: address {:#06X} accessed by {:04X} at {:#06X}",
+                    address, opcode, pc
+                )
+            }
+            Chip8Error::PcOutOfBounds(pc) => {
+                write!(f, "PcOutOfBounds error: program counter at {:#06X}", pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::RomRead(e) => Some(e),
+            _ => None,
         }
     }
 }
 
-type Result<T> = std::result::Result<T, Chip8Error>;
+pub type Result<T> = std::result::Result<T, Chip8Error>;
+
+/// What happens when an instruction addresses memory past `MEMORY_SIZE`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutOfBoundsPolicy {
+    /// Stop with `Chip8Error::MemoryOutOfBounds` or `Chip8Error::PcOutOfBounds`.
+    Error,
+    /// Wrap the address around to the start of memory.
+    Wrap,
+}
+
+impl std::str::FromStr for OutOfBoundsPolicy {
+    type Err = String;
 
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(OutOfBoundsPolicy::Error),
+            "wrap" => Ok(OutOfBoundsPolicy::Wrap),
+            _ => Err(format!(
+                "Unknown out of bounds policy '{}': expected error or wrap",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryAccessKind {
+    Read,
+    Write,
+}
+
+/// A byte of memory an instruction read or wrote as data, recorded while memory observation is on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryAccess {
+    pub address: usize,
+    pub kind: MemoryAccessKind,
+    pub value: u8,
+}
+
+/// A xorshift64* random number generator for `CXNN`. Its whole state is one
+/// `u64`, so unlike the `rand` generators it can be saved with the machine.
+#[derive(Debug, Clone)]
+struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    fn new(seed: u64) -> Self {
+        // An all-zero state would only ever produce zeros.
+        Self { state: seed.max(1) }
+    }
+
+    fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+}
+
+/// A decoded 16 bit instruction, split into its nibbles.
 #[derive(Debug, Clone)]
 pub struct Opcode {
     raw: u16,
@@ -39,7 +215,7 @@ pub struct Opcode {
 }
 
 impl Opcode {
-    fn new(raw: u16) -> Self {
+    pub fn new(raw: u16) -> Self {
         return Opcode {
             raw,
             op_type: ((raw & 0xF000) >> 12) as u8,
@@ -49,63 +225,207 @@ impl Opcode {
         };
     }
 
-    fn get_nn(&self) -> u8 {
+    pub fn raw(&self) -> u16 {
+        self.raw
+    }
+    pub fn op_type(&self) -> u8 {
+        self.op_type
+    }
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+    pub fn n(&self) -> u8 {
+        self.n
+    }
+    pub fn get_nn(&self) -> u8 {
         (self.raw & 0x00FF) as u8
     }
-    fn get_nnn(&self) -> u16 {
+    pub fn get_nnn(&self) -> u16 {
         (self.raw & 0x0FFF) as u16
     }
 }
 
 pub struct Chip8 {
-    io: Rc<RefCell<chip8_io::Chip8IO>>,
+    io: Rc<RefCell<dyn Backend>>,
+    framebuffer: Framebuffer,
+    quirks: Quirks,
+    machine_code_policy: MachineCodePolicy,
+    out_of_bounds_policy: OutOfBoundsPolicy,
+    skipped_call_addresses: HashSet<u16>,
+    skipped_calls: Vec<SkippedCall>,
+    drawn_this_frame: bool,
+    exited: bool,
     pc: usize,
     i: usize,
     delay_timer: u8,
     sound_timer: u8,
     registers: [u8; NUM_REGISTERS],
+    rpl_flags: [u8; NUM_REGISTERS],
     stack: Vec<usize>,
     memory: [u8; MEMORY_SIZE],
-    rng: rand::rngs::ThreadRng,
-    distrib: Uniform<u16>,
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+    rng: Xorshift,
+    rom_hash: u64,
+    observe_memory: bool,
+    memory_accesses: Vec<MemoryAccess>,
+    code_tracker: Option<CodeTracker>,
+    /// The address and opcode of the instruction being executed, for error messages.
+    instruction_pc: usize,
+    instruction_raw: u16,
 }
 
 impl Chip8 {
-    pub fn new(io: &Rc<RefCell<chip8_io::Chip8IO>>) -> Self {
-        return Chip8 {
-            io: Rc::clone(io),
+    /// Creates a machine in its power-on state with `FONT` and `BIG_FONT` loaded, drawing to and reading keys from `io`.
+    pub fn new<B: Backend + 'static>(io: &Rc<RefCell<B>>) -> Self {
+        let mut chip8 = Chip8 {
+            io: Rc::clone(io) as Rc<RefCell<dyn Backend>>,
+            framebuffer: Framebuffer::new(),
+            quirks: Quirks::default(),
+            machine_code_policy: MachineCodePolicy::Error,
+            out_of_bounds_policy: OutOfBoundsPolicy::Error,
+            skipped_call_addresses: HashSet::new(),
+            skipped_calls: Vec::new(),
+            drawn_this_frame: false,
+            exited: false,
             pc: ROM_START_ADDR,
             i: 0,
             delay_timer: 0,
             sound_timer: 0,
             registers: [0; NUM_REGISTERS],
+            rpl_flags: [0; NUM_REGISTERS],
             stack: Vec::new(),
             memory: [0; MEMORY_SIZE],
-            rng: rand::rng(),
-            distrib: Uniform::new(0, 256).unwrap(),
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            rng: Xorshift::new(rand::random()),
+            rom_hash: savestate::rom_hash(&[]),
+            observe_memory: false,
+            memory_accesses: Vec::new(),
+            code_tracker: None,
+            instruction_pc: ROM_START_ADDR,
+            instruction_raw: 0,
         };
+        chip8.memory[FONT_START_ADDR..FONT_START_ADDR + FONT_SIZE].copy_from_slice(&FONT);
+        chip8.memory[BIG_FONT_START_ADDR..BIG_FONT_START_ADDR + BIG_FONT_SIZE]
+            .copy_from_slice(&BIG_FONT);
+        chip8
     }
 
     fn set_vf(&mut self, value: u8) {
         self.registers[0xF] = value;
     }
 
+    fn reset_vf_if_quirk(&mut self) {
+        if self.quirks.vf_reset {
+            self.set_vf(0);
+        }
+    }
+
+    /// The value `8XY6`/`8XYE` shift, VX or VY depending on the shift quirk.
+    fn shift_source(&self, opcode: &Opcode) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.registers[opcode.y as usize]
+        } else {
+            self.registers[opcode.x as usize]
+        }
+    }
+
+    /// Like FX1E, I wraps at its width instead of pointing past it.
+    fn advance_i_after_load_store(&mut self, opcode: &Opcode) {
+        match self.quirks.load_store {
+            LoadStoreQuirk::Unchanged => {}
+            LoadStoreQuirk::IncrementByX => self.i += opcode.x as usize,
+            LoadStoreQuirk::IncrementByXPlusOne => self.i += opcode.x as usize + 1,
+        }
+        self.i &= self.index_mask();
+    }
+
+    /// The bits I can hold, see `Quirks::wide_index`.
+    fn index_mask(&self) -> usize {
+        if self.quirks.wide_index {
+            MEMORY_SIZE - 1
+        } else {
+            0xFFF
+        }
+    }
+
     fn skip_pc(&mut self) {
         self.pc += 2;
     }
 
+    /// Skips the next instruction, which is 4 bytes long if it is `F000 NNNN`.
+    fn skip_instruction(&mut self) {
+        // A next instruction out of bounds is not F000, fetching it reports the error.
+        if self.read_word(self.pc).ok() == Some(LONG_LOAD_OPCODE) {
+            self.pc += 2;
+        }
+        self.skip_pc();
+    }
+
+    /// Maps an address an instruction uses to an index into memory, following the out of bounds policy.
+    fn resolve_address(&self, address: usize) -> Result<usize> {
+        if address < MEMORY_SIZE {
+            return Ok(address);
+        }
+        match self.out_of_bounds_policy {
+            OutOfBoundsPolicy::Wrap => Ok(address % MEMORY_SIZE),
+            OutOfBoundsPolicy::Error => Err(Chip8Error::MemoryOutOfBounds {
+                address,
+                pc: self.instruction_pc,
+                opcode: self.instruction_raw,
+            }),
+        }
+    }
+
+    fn read_word(&self, address: usize) -> Result<u16> {
+        let high = self.memory[self.resolve_address(address)?];
+        let low = self.memory[self.resolve_address(address + 1)?];
+        Ok(((high as u16) << 8) | (low as u16))
+    }
+
+    /// Reads a data byte for an instruction. Instruction fetches use `read_word` instead.
+    fn read_byte(&mut self, address: usize) -> Result<u8> {
+        let address = self.resolve_address(address)?;
+        let value = self.memory[address];
+        if self.observe_memory {
+            self.memory_accesses.push(MemoryAccess {
+                address,
+                kind: MemoryAccessKind::Read,
+                value,
+            });
+        }
+        Ok(value)
+    }
+
+    /// Writes a data byte for an instruction.
+    fn write_byte(&mut self, address: usize, value: u8) -> Result<()> {
+        let address = self.resolve_address(address)?;
+        self.memory[address] = value;
+        if let Some(tracker) = &mut self.code_tracker {
+            tracker.record_write(address, Some(self.instruction_pc));
+        }
+        if self.observe_memory {
+            self.memory_accesses.push(MemoryAccess {
+                address,
+                kind: MemoryAccessKind::Write,
+                value,
+            });
+        }
+        Ok(())
+    }
+
     fn skip_pc_back(&mut self) {
         self.pc -= 2;
     }
 
     fn exec_op_type0(&mut self, opcode: &Opcode) -> Result<()> {
-        match opcode.get_nn() {
+        match opcode.get_nnn() {
             0x0E0 => {
-                for row in 0..chip8_io::DISPLAY_HEIGHT {
-                    for col in 0..chip8_io::DISPLAY_WIDTH {
-                        self.io.borrow_mut().write_pixel(row, col, false);
-                    }
-                }
+                self.framebuffer.clear();
                 Ok(())
             }
             0x0EE => {
@@ -115,34 +435,110 @@ impl Chip8 {
                     .ok_or(Chip8Error::StackUnderflow(opcode.clone()))?;
                 Ok(())
             }
-            _ => Err(Chip8Error::InvaidOpcode(opcode.raw)),
+            0x0C0..=0x0CF => {
+                self.framebuffer.scroll_down(opcode.n as usize);
+                Ok(())
+            }
+            0x0D0..=0x0DF => {
+                self.framebuffer.scroll_up(opcode.n as usize);
+                Ok(())
+            }
+            0x0FB => {
+                self.framebuffer.scroll_right(4);
+                Ok(())
+            }
+            0x0FC => {
+                self.framebuffer.scroll_left(4);
+                Ok(())
+            }
+            0x0FD => {
+                self.exited = true;
+                Ok(())
+            }
+            0x0FE => {
+                self.framebuffer.set_hires(false);
+                Ok(())
+            }
+            0x0FF => {
+                self.framebuffer.set_hires(true);
+                Ok(())
+            }
+            _ => self.exec_machine_code(opcode),
         }
     }
 
+    /// Handles `0NNN` according to the machine code policy.
+    fn exec_machine_code(&mut self, opcode: &Opcode) -> Result<()> {
+        if self.machine_code_policy == MachineCodePolicy::Error {
+            return Err(Chip8Error::InvalidOpcode(opcode.raw));
+        }
+        let address = opcode.get_nnn();
+        if self.skipped_call_addresses.insert(address) {
+            self.skipped_calls.push(SkippedCall {
+                address,
+                pc: self.pc - 2,
+            });
+        }
+        Ok(())
+    }
+
     fn exec_op_type1(&mut self, opcode: &Opcode) {
         self.pc = opcode.get_nnn() as usize;
     }
 
-    fn exec_op_type2(&mut self, opcode: &Opcode) {
+    fn exec_op_type2(&mut self, opcode: &Opcode) -> Result<()> {
+        if self.stack.len() >= self.quirks.stack_depth {
+            return Err(Chip8Error::StackOverflow(opcode.clone()));
+        }
         self.stack.push(self.pc);
         self.pc = opcode.get_nnn() as usize;
+        Ok(())
     }
 
     fn exec_op_type3(&mut self, opcode: &Opcode) {
         if self.registers[opcode.x as usize] == opcode.get_nn() {
-            self.skip_pc();
+            self.skip_instruction();
         }
     }
 
     fn exec_op_type4(&mut self, opcode: &Opcode) {
         if self.registers[opcode.x as usize] != opcode.get_nn() {
-            self.skip_pc();
+            self.skip_instruction();
         }
     }
 
-    fn exec_op_type5(&mut self, opcode: &Opcode) {
-        if self.registers[opcode.x as usize] == self.registers[opcode.y as usize] {
-            self.skip_pc();
+    fn exec_op_type5(&mut self, opcode: &Opcode) -> Result<()> {
+        let x = opcode.x as usize;
+        let y = opcode.y as usize;
+        match opcode.n {
+            0x0 => {
+                if self.registers[x] == self.registers[y] {
+                    self.skip_instruction();
+                }
+                Ok(())
+            }
+            0x2 => {
+                for (offset, register) in Self::register_range(x, y).enumerate() {
+                    self.write_byte(self.i + offset, self.registers[register])?;
+                }
+                Ok(())
+            }
+            0x3 => {
+                for (offset, register) in Self::register_range(x, y).enumerate() {
+                    self.registers[register] = self.read_byte(self.i + offset)?;
+                }
+                Ok(())
+            }
+            _ => Err(Chip8Error::InvalidOpcode(opcode.raw)),
+        }
+    }
+
+    /// The registers `5XY2`/`5XY3` walk through, from VX to VY in either direction.
+    fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
         }
     }
 
@@ -163,14 +559,17 @@ impl Chip8 {
             }
             0x1 => {
                 self.registers[opcode.x as usize] |= self.registers[opcode.y as usize];
+                self.reset_vf_if_quirk();
                 Ok(())
             }
             0x2 => {
                 self.registers[opcode.x as usize] &= self.registers[opcode.y as usize];
+                self.reset_vf_if_quirk();
                 Ok(())
             }
             0x3 => {
                 self.registers[opcode.x as usize] ^= self.registers[opcode.y as usize];
+                self.reset_vf_if_quirk();
                 Ok(())
             }
             0x4 => {
@@ -200,8 +599,9 @@ impl Chip8 {
                 Ok(())
             }
             0x6 => {
-                let vf_value = self.registers[opcode.x as usize] & 0x01;
-                self.registers[opcode.x as usize] = self.registers[opcode.x as usize] >> 1;
+                let source = self.shift_source(opcode);
+                let vf_value = source & 0x01;
+                self.registers[opcode.x as usize] = source >> 1;
                 self.set_vf(vf_value);
                 Ok(())
             }
@@ -218,19 +618,24 @@ impl Chip8 {
                 Ok(())
             }
             0xE => {
-                let vf_value = (self.registers[opcode.x as usize] & 0x80) >> 7;
-                self.registers[opcode.x as usize] = self.registers[opcode.x as usize] << 1;
+                let source = self.shift_source(opcode);
+                let vf_value = (source & 0x80) >> 7;
+                self.registers[opcode.x as usize] = source << 1;
                 self.set_vf(vf_value);
                 Ok(())
             }
-            _ => Err(Chip8Error::InvaidOpcode(opcode.raw)),
+            _ => Err(Chip8Error::InvalidOpcode(opcode.raw)),
         }
     }
 
-    fn exec_op_type9(&mut self, opcode: &Opcode) {
+    fn exec_op_type9(&mut self, opcode: &Opcode) -> Result<()> {
+        if opcode.n != 0 {
+            return Err(Chip8Error::InvalidOpcode(opcode.raw));
+        }
         if self.registers[opcode.x as usize] != self.registers[opcode.y as usize] {
-            self.skip_pc();
+            self.skip_instruction();
         }
+        Ok(())
     }
 
     fn exec_op_type10(&mut self, opcode: &Opcode) {
@@ -238,83 +643,135 @@ impl Chip8 {
     }
 
     fn exec_op_type11(&mut self, opcode: &Opcode) {
-        self.pc = (opcode.get_nnn() + self.registers[0] as u16) as usize;
+        let offset_register = if self.quirks.jump_uses_vx {
+            opcode.x as usize
+        } else {
+            0
+        };
+        self.pc = (opcode.get_nnn() + self.registers[offset_register] as u16) as usize;
     }
 
     fn exec_op_type12(&mut self, opcode: &Opcode) {
-        self.registers[opcode.x as usize] =
-            (self.distrib.sample(&mut self.rng) as u8) & opcode.get_nn();
+        self.registers[opcode.x as usize] = self.rng.next_u8() & opcode.get_nn();
     }
 
-    fn exec_op_type13(&mut self, opcode: &Opcode) {
-        let x_coord = self.registers[opcode.x as usize] % (chip8_io::DISPLAY_WIDTH as u8);
-        let y_coord = self.registers[opcode.y as usize] % (chip8_io::DISPLAY_HEIGHT as u8);
+    fn exec_op_type13(&mut self, opcode: &Opcode) -> Result<()> {
+        if self.quirks.display_wait {
+            if self.drawn_this_frame {
+                self.skip_pc_back();
+                return Ok(());
+            }
+            self.drawn_this_frame = true;
+        }
+
+        let width = self.framebuffer.width();
+        let height = self.framebuffer.height();
+        let x_coord = self.registers[opcode.x as usize] as usize % width;
+        let y_coord = self.registers[opcode.y as usize] as usize % height;
         self.set_vf(0);
 
-        for i in 0..opcode.n {
-            let new_y_coord = y_coord + i;
-            if new_y_coord >= (chip8_io::DISPLAY_HEIGHT as u8) {
+        // DXY0 draws a 16x16 sprite stored as two bytes per row.
+        let (sprite_width, sprite_height) = if opcode.n == 0 {
+            (16, 16)
+        } else {
+            (8, opcode.n as usize)
+        };
+        let bytes_per_row = sprite_width / 8;
+        let sprite_size = bytes_per_row * sprite_height;
+
+        // Each selected plane consumes its own copy of the sprite, one after another.
+        let mut sprite_addr = self.i;
+        for plane in 0..NUM_PLANES {
+            if self.framebuffer.selected_planes() & (1 << plane) == 0 {
                 continue;
             }
-            for j in 0..8 {
-                let new_x_coord = x_coord + j;
-                if new_x_coord >= (chip8_io::DISPLAY_WIDTH as u8) {
-                    continue;
+            for i in 0..sprite_height {
+                let mut new_y_coord = y_coord + i;
+                if new_y_coord >= height {
+                    if self.quirks.clip_sprites {
+                        continue;
+                    }
+                    new_y_coord %= height;
                 }
-                let mask = 1 << (7 - j);
-                let sprite_color = (self.memory[self.i + i as usize] & mask) >> (7 - j);
-                let prev_frame_color = self
-                    .io
-                    .borrow_mut()
-                    .get_pixel_color(new_y_coord as usize, new_x_coord as usize);
-                if sprite_color == 1 {
-                    if prev_frame_color == self.io.borrow_mut().primary_color {
+                // The row's bytes packed into a u16 with the leftmost pixel in the top bit.
+                let row_addr = sprite_addr + i * bytes_per_row;
+                let mut sprite_row = 0u16;
+                for byte in 0..bytes_per_row {
+                    sprite_row |= (self.read_byte(row_addr + byte)? as u16) << (8 - 8 * byte);
+                }
+                for j in 0..sprite_width {
+                    let mut new_x_coord = x_coord + j;
+                    if new_x_coord >= width {
+                        if self.quirks.clip_sprites {
+                            continue;
+                        }
+                        new_x_coord %= width;
+                    }
+                    let sprite_color = (sprite_row >> (15 - j)) & 1;
+                    if sprite_color == 1 && self.framebuffer.toggle(plane, new_y_coord, new_x_coord)
+                    {
                         self.set_vf(1);
-                        self.io.borrow_mut().write_pixel(
-                            new_y_coord as usize,
-                            new_x_coord as usize,
-                            false,
-                        );
-                    } else {
-                        self.io.borrow_mut().write_pixel(
-                            new_y_coord as usize,
-                            new_x_coord as usize,
-                            true,
-                        );
                     }
                 }
             }
+            sprite_addr += sprite_size;
         }
+        Ok(())
     }
 
     fn exec_op_type14(&mut self, opcode: &Opcode) -> Result<()> {
-        match opcode.n {
-            0x1 => {
+        match opcode.get_nn() {
+            0xA1 => {
                 if !self
                     .io
                     .borrow_mut()
                     .is_key_pressed(self.registers[opcode.x as usize])
                 {
-                    self.skip_pc();
+                    self.skip_instruction();
                 }
                 Ok(())
             }
-            0xE => {
+            0x9E => {
                 if self
                     .io
                     .borrow_mut()
                     .is_key_pressed(self.registers[opcode.x as usize])
                 {
-                    self.skip_pc();
+                    self.skip_instruction();
                 }
                 Ok(())
             }
-            _ => Err(Chip8Error::InvaidOpcode(opcode.raw)),
+            _ => Err(Chip8Error::InvalidOpcode(opcode.raw)),
         }
     }
 
     fn exec_op_type15(&mut self, opcode: &Opcode) -> Result<()> {
         match opcode.get_nn() {
+            0x00 if opcode.x == 0 => {
+                self.i = self.read_word(self.pc)? as usize;
+                self.skip_pc();
+                Ok(())
+            }
+            0x01 => {
+                self.framebuffer.select_planes(opcode.x);
+                Ok(())
+            }
+            0x02 if opcode.x == 0 => {
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_byte(self.i + offset)?;
+                }
+                self.audio_pattern = Some(pattern);
+                self.io.borrow_mut().set_audio_pattern(&pattern, self.pitch);
+                Ok(())
+            }
+            0x3A => {
+                self.pitch = self.registers[opcode.x as usize];
+                if let Some(pattern) = self.audio_pattern.as_ref() {
+                    self.io.borrow_mut().set_audio_pattern(pattern, self.pitch);
+                }
+                Ok(())
+            }
             0x7 => {
                 self.registers[opcode.x as usize] = self.delay_timer;
                 Ok(())
@@ -329,10 +786,10 @@ impl Chip8 {
             }
             0x1E => {
                 self.i += self.registers[opcode.x as usize] as usize;
-                if self.i >= 0x1000 {
+                if self.i > self.index_mask() {
                     self.set_vf(1);
                 }
-                self.i &= 0xFFF;
+                self.i &= self.index_mask();
                 Ok(())
             }
             0x0A => {
@@ -352,7 +809,12 @@ impl Chip8 {
                 Ok(())
             }
             0x29 => {
-                self.i = FONT_START_ADDR + (self.registers[opcode.x as usize] * 5) as usize;
+                self.i = FONT_START_ADDR + (self.registers[opcode.x as usize] & 0xF) as usize * 5;
+                Ok(())
+            }
+            0x30 => {
+                self.i =
+                    BIG_FONT_START_ADDR + (self.registers[opcode.x as usize] & 0xF) as usize * 10;
                 Ok(())
             }
             0x33 => {
@@ -369,45 +831,205 @@ impl Chip8 {
                 }
 
                 for (index, &value) in digits.iter().rev().enumerate() {
-                    self.memory[self.i + index] = value;
+                    self.write_byte(self.i + index, value)?;
                 }
                 Ok(())
             }
             0x55 => {
                 for i in 0..opcode.x + 1 {
-                    self.memory[self.i + (i as usize)] = self.registers[i as usize];
+                    self.write_byte(self.i + (i as usize), self.registers[i as usize])?;
                 }
+                self.advance_i_after_load_store(opcode);
                 Ok(())
             }
             0x65 => {
                 for i in 0..opcode.x + 1 {
-                    self.registers[i as usize] = self.memory[self.i + i as usize];
+                    self.registers[i as usize] = self.read_byte(self.i + i as usize)?;
                 }
+                self.advance_i_after_load_store(opcode);
+                Ok(())
+            }
+            0x75 => {
+                self.rpl_flags[..=opcode.x as usize]
+                    .copy_from_slice(&self.registers[..=opcode.x as usize]);
                 Ok(())
             }
-            _ => Err(Chip8Error::InvaidOpcode(opcode.raw)),
+            0x85 => {
+                self.registers[..=opcode.x as usize]
+                    .copy_from_slice(&self.rpl_flags[..=opcode.x as usize]);
+                Ok(())
+            }
+            _ => Err(Chip8Error::InvalidOpcode(opcode.raw)),
         }
     }
 
-    pub fn load_rom(&mut self, rom_file: &mut std::fs::File) {
-        let len = rom_file.seek(SeekFrom::End(0)).expect("Failed to load ROM");
-
+    pub fn load_rom(&mut self, rom_file: &mut std::fs::File) -> Result<()> {
+        let mut rom = Vec::new();
         rom_file
             .seek(SeekFrom::Start(0))
-            .expect("Failed to load ROM");
+            .and_then(|_| rom_file.read_to_end(&mut rom))
+            .map_err(Chip8Error::RomRead)?;
+        self.load_rom_bytes(&rom)
+    }
 
-        rom_file
-            .read_exact(&mut self.memory[ROM_START_ADDR..ROM_START_ADDR + len as usize])
-            .expect("Failed to load ROM");
+    /// Copies `rom` into memory at `ROM_START_ADDR`.
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<()> {
+        if rom.len() > MEMORY_SIZE - ROM_START_ADDR {
+            return Err(Chip8Error::RomTooLarge(rom.len()));
+        }
+        self.memory[ROM_START_ADDR..ROM_START_ADDR + rom.len()].copy_from_slice(rom);
+        self.rom_hash = savestate::rom_hash(rom);
+        if let Some(tracker) = &mut self.code_tracker {
+            tracker.reset();
+        }
+        Ok(())
+    }
+
+    /// The hash save states use to check they belong to the loaded ROM.
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Reseeds the `CXNN` random number generator, e.g. for reproducible runs.
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng = Xorshift::new(seed);
+    }
+
+    /// Serializes the complete machine state, including the keypad of the backend.
+    /// Quirks and other settings are not part of the state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new(self.rom_hash);
+        writer.write_u32(self.pc as u32);
+        writer.write_u32(self.i as u32);
+        writer.write_u8(self.delay_timer);
+        writer.write_u8(self.sound_timer);
+        writer.write_bytes(&self.registers);
+        writer.write_bytes(&self.rpl_flags);
+        // A fixed number of slots keeps the state the same size as the call depth
+        // changes, so rewind snapshots of consecutive frames stay small deltas.
+        let slots = self.stack.len().max(self.quirks.stack_depth);
+        writer.write_u16(self.stack.len() as u16);
+        writer.write_u16(slots as u16);
+        for slot in 0..slots {
+            writer.write_u32(self.stack.get(slot).copied().unwrap_or_default() as u32);
+        }
+        writer.write_bytes(&self.memory);
+        self.framebuffer.write_state(&mut writer);
+        writer.write_bool(self.audio_pattern.is_some());
+        writer.write_bytes(&self.audio_pattern.unwrap_or_default());
+        writer.write_u8(self.pitch);
+        writer.write_u64(self.rng.state);
+        writer.write_bool(self.exited);
+        writer.write_bool(self.drawn_this_frame);
+
+        let io = self.io.borrow();
+        let keys = (0..NUM_KEYS as u8)
+            .filter(|&key| io.is_key_pressed(key))
+            .fold(0u16, |keys, key| keys | (1 << key));
+        writer.write_u16(keys);
+        writer.finish()
+    }
+
+    /// Restores a state written by `save_state`. On error the machine is left unchanged.
+    pub fn load_state(&mut self, state: &[u8]) -> std::result::Result<(), SaveStateError> {
+        let mut reader = StateReader::new(state, self.rom_hash)?;
+        // PC and return addresses may point past memory, the next fetch reports or wraps them.
+        let pc = reader.read_u32()? as usize;
+        let i = reader.read_u32()? as usize;
+        if i >= MEMORY_SIZE {
+            return Err(SaveStateError::InvalidValue("address"));
+        }
+        let delay_timer = reader.read_u8()?;
+        let sound_timer = reader.read_u8()?;
+        let registers = reader.read_array()?;
+        let rpl_flags = reader.read_array()?;
+        let stack_len = reader.read_u16()? as usize;
+        let stack_slots = reader.read_u16()? as usize;
+        if stack_len > stack_slots {
+            return Err(SaveStateError::InvalidValue("stack length"));
+        }
+        let mut stack = (0..stack_slots)
+            .map(|_| Ok(reader.read_u32()? as usize))
+            .collect::<std::result::Result<Vec<usize>, SaveStateError>>()?;
+        stack.truncate(stack_len);
+        let memory = reader.read_array()?;
+        let framebuffer = Framebuffer::read_state(&mut reader)?;
+        let has_audio_pattern = reader.read_bool()?;
+        let audio_pattern = reader.read_array()?;
+        let pitch = reader.read_u8()?;
+        let rng_state = reader.read_u64()?;
+        let exited = reader.read_bool()?;
+        let drawn_this_frame = reader.read_bool()?;
+        let keys = reader.read_u16()?;
+        reader.finish()?;
+
+        self.pc = pc;
+        self.i = i;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.registers = registers;
+        self.rpl_flags = rpl_flags;
+        self.stack = stack;
+        self.memory = memory;
+        self.framebuffer = framebuffer;
+        self.audio_pattern = has_audio_pattern.then_some(audio_pattern);
+        self.pitch = pitch;
+        self.rng = Xorshift::new(rng_state);
+        self.exited = exited;
+        self.drawn_this_frame = drawn_this_frame;
+        if let Some(tracker) = &mut self.code_tracker {
+            tracker.reset();
+        }
+
+        let mut io = self.io.borrow_mut();
+        for key in 0..NUM_KEYS as u8 {
+            io.set_key(key, keys & (1 << key) != 0);
+        }
+        if let Some(pattern) = self.audio_pattern.as_ref() {
+            io.set_audio_pattern(pattern, self.pitch);
+        }
+        Ok(())
     }
 
-    pub fn load_font(&mut self, font_buffer: &[u8], font_size: usize) {
+    pub fn load_font(&mut self, font_buffer: &[u8], font_size: usize) -> Result<()> {
+        if font_size > font_buffer.len() || FONT_START_ADDR + font_size > ROM_START_ADDR {
+            return Err(Chip8Error::InvalidFontSize {
+                size: font_size,
+                buffer_len: font_buffer.len(),
+            });
+        }
         self.memory[FONT_START_ADDR..FONT_START_ADDR + font_size]
-            .as_mut()
-            .copy_from_slice(font_buffer)
+            .copy_from_slice(&font_buffer[..font_size]);
+        Ok(())
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn machine_code_policy(&self) -> MachineCodePolicy {
+        self.machine_code_policy
+    }
+
+    pub fn set_machine_code_policy(&mut self, policy: MachineCodePolicy) {
+        self.machine_code_policy = policy;
+    }
+
+    pub fn out_of_bounds_policy(&self) -> OutOfBoundsPolicy {
+        self.out_of_bounds_policy
+    }
+
+    pub fn set_out_of_bounds_policy(&mut self, policy: OutOfBoundsPolicy) {
+        self.out_of_bounds_policy = policy;
     }
 
     pub fn update_timers(&mut self) {
+        self.drawn_this_frame = false;
+
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -419,29 +1041,179 @@ impl Chip8 {
         }
     }
 
+    /// Runs one 60 Hz frame: ticks the timers, then executes `instructions` instructions.
+    pub fn run_frame(&mut self, instructions: u32) -> Result<()> {
+        self.update_timers();
+        for _ in 0..instructions {
+            self.run_cycle()?;
+        }
+        Ok(())
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn i(&self) -> usize {
+        self.i
+    }
+
+    pub fn registers(&self) -> &[u8; NUM_REGISTERS] {
+        &self.registers
+    }
+
+    /// Return addresses pushed by `2NNN`, innermost last.
+    pub fn stack(&self) -> &[usize] {
+        &self.stack
+    }
+
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        &self.memory
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// The SUPER-CHIP RPL user flags saved by `FX75`.
+    pub fn rpl_flags(&self) -> &[u8; NUM_REGISTERS] {
+        &self.rpl_flags
+    }
+
+    /// Whether the program has stopped itself with `00FD`.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// Sets I, wrapped to 16 bits.
+    pub fn set_i(&mut self, i: usize) {
+        self.i = i & (MEMORY_SIZE - 1);
+    }
+
+    pub fn set_register(&mut self, register: usize, value: u8) {
+        self.registers[register] = value;
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// Drops return addresses until at most `len` remain. The stack cannot be grown this way.
+    pub fn truncate_stack(&mut self, len: usize) {
+        self.stack.truncate(len);
+    }
+
+    /// Copies `bytes` into memory starting at `address`, for debuggers and tools.
+    /// Panics if the range does not fit in `MEMORY_SIZE`.
+    pub fn write_memory(&mut self, address: usize, bytes: &[u8]) {
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
+        if let Some(tracker) = &mut self.code_tracker {
+            for address in address..address + bytes.len() {
+                tracker.record_write(address, None);
+            }
+        }
+    }
+
+    /// Turns tracking of executed and written bytes on or off, to detect self-modifying code.
+    pub fn set_track_code(&mut self, track: bool) {
+        self.code_tracker = track.then(CodeTracker::new);
+    }
+
+    pub fn code_tracker(&self) -> Option<&CodeTracker> {
+        self.code_tracker.as_ref()
+    }
+
+    /// Returns and forgets the self-modifying code events since the last call,
+    /// empty if tracking is off. A decode cache should drop the entries covering
+    /// every overwritten address, and everything after `load_state` or `load_rom_bytes`.
+    pub fn take_code_events(&mut self) -> Vec<CodeEvent> {
+        self.code_tracker
+            .as_mut()
+            .map(CodeTracker::take_events)
+            .unwrap_or_default()
+    }
+
+    /// Returns and forgets the machine code calls skipped since the last call,
+    /// oldest first. Only the first call to each address is recorded.
+    pub fn take_skipped_calls(&mut self) -> Vec<SkippedCall> {
+        std::mem::take(&mut self.skipped_calls)
+    }
+
+    /// Turns recording of the data bytes instructions read and write on or off.
+    /// Recorded accesses accumulate until `take_memory_accesses` is called.
+    pub fn set_observe_memory(&mut self, observe: bool) {
+        self.observe_memory = observe;
+        if !observe {
+            self.memory_accesses.clear();
+        }
+    }
+
+    /// Returns and forgets the memory accesses recorded since the last call, oldest first.
+    pub fn take_memory_accesses(&mut self) -> Vec<MemoryAccess> {
+        std::mem::take(&mut self.memory_accesses)
+    }
+
+    /// Presses or releases one of the 16 keys on the backend's keypad.
+    pub fn set_key(&mut self, key_num: u8, pressed: bool) {
+        self.io.borrow_mut().set_key(key_num, pressed);
+    }
+
+    /// Executes the instruction at the program counter. Does nothing once the program has exited.
     pub fn run_cycle(&mut self) -> Result<()> {
-        let opcod_raw = ((self.memory[self.pc] as u16) << 8) | (self.memory[self.pc + 1] as u16);
+        if self.exited {
+            return Ok(());
+        }
+
+        if self.pc + 1 >= MEMORY_SIZE {
+            match self.out_of_bounds_policy {
+                OutOfBoundsPolicy::Wrap => self.pc %= MEMORY_SIZE,
+                OutOfBoundsPolicy::Error => return Err(Chip8Error::PcOutOfBounds(self.pc)),
+            }
+        }
+        self.instruction_pc = self.pc;
+        let opcod_raw = self.read_word(self.pc)?;
+        self.instruction_raw = opcod_raw;
+        if let Some(tracker) = &mut self.code_tracker {
+            let len = if opcod_raw == LONG_LOAD_OPCODE { 4 } else { 2 };
+            tracker.record_execution(self.pc, len);
+        }
         let opcode = Opcode::new(opcod_raw);
         self.skip_pc();
 
         match opcode.op_type {
             0x0 => self.exec_op_type0(&opcode)?,
             0x1 => self.exec_op_type1(&opcode),
-            0x2 => self.exec_op_type2(&opcode),
+            0x2 => self.exec_op_type2(&opcode)?,
             0x3 => self.exec_op_type3(&opcode),
             0x4 => self.exec_op_type4(&opcode),
-            0x5 => self.exec_op_type5(&opcode),
+            0x5 => self.exec_op_type5(&opcode)?,
             0x6 => self.exec_op_type6(&opcode),
             0x7 => self.exec_op_type7(&opcode),
             0x8 => self.exec_op_type8(&opcode)?,
-            0x9 => self.exec_op_type9(&opcode),
+            0x9 => self.exec_op_type9(&opcode)?,
             0xA => self.exec_op_type10(&opcode),
             0xB => self.exec_op_type11(&opcode),
             0xC => self.exec_op_type12(&opcode),
-            0xD => self.exec_op_type13(&opcode),
+            0xD => self.exec_op_type13(&opcode)?,
             0xE => self.exec_op_type14(&opcode)?,
             0xF => self.exec_op_type15(&opcode)?,
-            _ => Err(Chip8Error::InvaidOpcode(opcode.raw))?,
+            _ => Err(Chip8Error::InvalidOpcode(opcode.raw))?,
         };
 
         Ok(())
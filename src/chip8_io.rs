@@ -1,9 +1,15 @@
+use crate::backend::{AudioBackend, DisplayBackend, Hotkey, InputBackend, NUM_KEYS};
+use crate::chip8::AUDIO_PATTERN_SIZE;
+use crate::filters::{self, Filter};
+use crate::frame_blend::{FrameBlend, FrameBlendMode};
+use crate::framebuffer::{Framebuffer, LORES_HEIGHT, LORES_WIDTH};
+use crate::palette::Palette;
+use crate::phosphor::{DEFAULT_FADE_FRAMES, PhosphorFade};
+use crate::upscale::{self, Upscaler};
+use sdl2::keyboard::Scancode;
 use sdl2::{audio::*, render::*, video::*};
 use std::collections::HashMap;
 
-pub const DISPLAY_WIDTH: usize = 64;
-pub const DISPLAY_HEIGHT: usize = 32;
-const NUM_KEYS: usize = 16;
 const KEYS: [&str; NUM_KEYS] = [
     "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "A", "B", "C", "D", "E", "F",
 ];
@@ -46,6 +52,11 @@ struct SquareWave {
     phase: f32,
     phase_increment: f32,
     volume: f32,
+    sample_rate: f32,
+    /// An XO-CHIP audio pattern, played instead of the 440 Hz tone once a program sets one.
+    pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pattern_position: f32,
+    pattern_increment: f32,
 }
 
 impl AudioCallback for SquareWave {
@@ -53,11 +64,21 @@ impl AudioCallback for SquareWave {
 
     fn callback(&mut self, buffer: &mut [Self::Channel]) {
         for i in buffer.iter_mut() {
-            self.phase += self.phase_increment;
-            if self.phase >= 1f32 {
-                self.phase -= 1f32
-            }
-            let sample = if self.phase < 0.5 {
+            let high = if let Some(pattern) = self.pattern.as_ref() {
+                let bit = self.pattern_position as usize;
+                self.pattern_position += self.pattern_increment;
+                if self.pattern_position >= (AUDIO_PATTERN_SIZE * 8) as f32 {
+                    self.pattern_position -= (AUDIO_PATTERN_SIZE * 8) as f32
+                }
+                (pattern[bit / 8] >> (7 - bit % 8)) & 1 == 1
+            } else {
+                self.phase += self.phase_increment;
+                if self.phase >= 1f32 {
+                    self.phase -= 1f32
+                }
+                self.phase < 0.5
+            };
+            let sample = if high {
                 (i16::max_value() as f32) * self.volume
             } else {
                 (i16::min_value() as f32) * self.volume
@@ -71,6 +92,8 @@ pub struct Sdl2Mngr {
     sdl_context: sdl2::Sdl,
     canvas: Canvas<Window>,
     texture: DroppableTexture,
+    texture_width: usize,
+    texture_height: usize,
     audio_device: Option<AudioDevice<SquareWave>>,
 }
 
@@ -89,6 +112,10 @@ fn create_audio_device(sdl_context: &sdl2::Sdl) -> Option<AudioDevice<SquareWave
                 phase: 0.0,
                 phase_increment: 440.0 / spec.freq as f32,
                 volume: 0.05,
+                sample_rate: spec.freq as f32,
+                pattern: None,
+                pattern_position: 0.0,
+                pattern_increment: 0.0,
             })
             .ok()?,
     )
@@ -102,14 +129,25 @@ fn create_window(sdl_context: &sdl2::Sdl, scale_factor: u32) -> Window {
     video_subsystem
         .window(
             "Chip8 Window",
-            (DISPLAY_WIDTH as u32) * scale_factor,
-            (DISPLAY_HEIGHT as u32) * scale_factor,
+            (LORES_WIDTH as u32) * scale_factor,
+            (LORES_HEIGHT as u32) * scale_factor,
         )
         .position_centered()
         .build()
         .expect("Failed to create a window.")
 }
 
+fn create_texture(canvas: &Canvas<Window>, width: usize, height: usize) -> Texture {
+    canvas
+        .texture_creator()
+        .create_texture_streaming(
+            sdl2::pixels::PixelFormatEnum::RGBA32,
+            width as u32,
+            height as u32,
+        )
+        .expect("Failed to create texture.")
+}
+
 impl Sdl2Mngr {
     fn new(scale_factor: u32) -> Self {
         let sdl_context = sdl2::init().expect("Failed to intialize the SDL2 Library.");
@@ -118,14 +156,7 @@ impl Sdl2Mngr {
             .into_canvas()
             .build()
             .expect("Failed to create canvas.");
-        let texture_creator = canvas.texture_creator();
-        let texture = texture_creator
-            .create_texture_streaming(
-                sdl2::pixels::PixelFormatEnum::RGBA32,
-                DISPLAY_WIDTH as u32,
-                DISPLAY_HEIGHT as u32,
-            )
-            .expect("Failed to create texture.");
+        let texture = create_texture(&canvas, LORES_WIDTH, LORES_HEIGHT);
 
         let audio_device = create_audio_device(&sdl_context);
 
@@ -133,28 +164,50 @@ impl Sdl2Mngr {
             sdl_context,
             canvas,
             texture: DroppableTexture::new(texture),
+            texture_width: LORES_WIDTH,
+            texture_height: LORES_HEIGHT,
             audio_device,
         };
     }
+
+    /// Replaces the texture when the display resolution changes. The window keeps its size.
+    fn resize_texture(&mut self, width: usize, height: usize) {
+        if (width, height) == (self.texture_width, self.texture_height) {
+            return;
+        }
+        self.texture = DroppableTexture::new(create_texture(&self.canvas, width, height));
+        self.texture_width = width;
+        self.texture_height = height;
+    }
 }
 
+/// SDL2 implementation of `Backend`: a window for the display, the keyboard for input and a square wave for audio.
 pub struct Chip8IO {
-    pub primary_color: u32,
-    pub secondary_color: u32,
+    /// The palettes `PALETTE_SCANCODE` cycles through.
+    palettes: Vec<Palette>,
+    palette_index: usize,
     keys_pressed: HashMap<&'static str, bool>,
-    display_buffer: [u8; DISPLAY_HEIGHT * DISPLAY_WIDTH * 4],
+    pending_hotkeys: Vec<Hotkey>,
+    rewind_held: bool,
+    /// The color index of each pixel of the current frame.
+    frame_indices: Vec<u8>,
+    frame_blend: Option<FrameBlend>,
+    /// The color of each pixel of the current frame, before it is written to `display_buffer`.
+    frame_colors: Vec<u32>,
+    phosphor: Option<PhosphorFade>,
+    /// The fade length `FADE_SCANCODE` switches on.
+    fade_frames: u32,
+    /// With nearest neighbour scaling and no filters the texture has the
+    /// framebuffer's size and SDL scales it, otherwise both run on the CPU at window resolution.
+    upscaler: Upscaler,
+    filters: Vec<Filter>,
+    scale_factor: usize,
+    /// `frame_colors` scaled up to window resolution.
+    scaled_colors: Vec<u32>,
+    display_buffer: Vec<u8>,
     sdl_mngr: Sdl2Mngr,
 }
 
-fn construct_color_from_slice(pixels: &[u8]) -> u32 {
-    let mut color: u32 = 0;
-    color |= (pixels[0] as u32) << 24;
-    color |= (pixels[1] as u32) << 16;
-    color |= (pixels[2] as u32) << 8;
-    color |= pixels[3] as u32;
-    color
-}
-
 fn write_color_to_slice(pixels: &mut [u8], color: u32) {
     pixels[0] = ((color & 0xFF000000) >> 24) as u8;
     pixels[1] = ((color & 0x00FF0000) >> 16) as u8;
@@ -163,46 +216,134 @@ fn write_color_to_slice(pixels: &mut [u8], color: u32) {
 }
 
 impl Chip8IO {
-    pub fn new(scale_factor: u32, primary_color: u32, secondary_color: u32) -> Self {
-        let mut display_buffer = [0u8; DISPLAY_WIDTH * DISPLAY_HEIGHT * 4];
-        for i in 0..DISPLAY_HEIGHT {
-            for j in 0..DISPLAY_WIDTH {
-                let index = ((i * DISPLAY_WIDTH) + j) * 4;
-                write_color_to_slice(&mut display_buffer[index..index + 4], secondary_color);
+    /// Shows the first of `palettes`, which must not be empty.
+    pub fn new(scale_factor: u32, palettes: Vec<Palette>) -> Self {
+        let background = palettes.first().expect("At least one palette").background();
+        let mut display_buffer = vec![0u8; LORES_WIDTH * LORES_HEIGHT * 4];
+        for i in 0..LORES_HEIGHT {
+            for j in 0..LORES_WIDTH {
+                let index = ((i * LORES_WIDTH) + j) * 4;
+                write_color_to_slice(&mut display_buffer[index..index + 4], background);
             }
         }
 
         return Self {
-            primary_color,
-            secondary_color,
+            palettes,
+            palette_index: 0,
             keys_pressed: KEYS
                 .iter()
                 .enumerate()
                 .map(|(_, &value)| (value, false))
                 .collect(),
+            pending_hotkeys: Vec::new(),
+            rewind_held: false,
+            frame_indices: Vec::new(),
+            frame_blend: None,
+            frame_colors: Vec::new(),
+            phosphor: None,
+            fade_frames: DEFAULT_FADE_FRAMES,
+            upscaler: Upscaler::Nearest,
+            filters: Vec::new(),
+            scale_factor: scale_factor as usize,
+            scaled_colors: Vec::new(),
             display_buffer,
             sdl_mngr: Sdl2Mngr::new(scale_factor),
         };
     }
 
-    pub fn write_pixel(&mut self, row: usize, col: usize, primary_color: bool) {
-        let index = ((row * DISPLAY_WIDTH) + col) * 4;
-        write_color_to_slice(
-            &mut self.display_buffer[index..index + 4],
-            if primary_color {
-                self.primary_color
-            } else {
-                self.secondary_color
-            },
-        );
+    /// Fades cleared pixels to the background over `frames` frames, or turns fading off with None.
+    pub fn set_phosphor_fade(&mut self, frames: Option<u32>) {
+        if let Some(frames) = frames {
+            self.fade_frames = frames;
+        }
+        self.phosphor = frames.map(PhosphorFade::new);
+    }
+
+    /// Blends each frame with the ones before it as `mode` describes, or turns blending off with None.
+    pub fn set_frame_blend(&mut self, mode: Option<FrameBlendMode>) {
+        self.frame_blend = mode.map(FrameBlend::new);
+    }
+
+    pub fn set_upscaler(&mut self, upscaler: Upscaler) {
+        self.upscaler = upscaler;
+    }
+
+    /// Post-processes every frame with `filters`, in order.
+    pub fn set_filters(&mut self, filters: Vec<Filter>) {
+        self.filters = filters;
     }
 
-    pub fn get_pixel_color(&self, row: usize, col: usize) -> u32 {
-        let index = ((row * DISPLAY_WIDTH) + col) * 4;
-        construct_color_from_slice(&self.display_buffer[index..index + 4])
+    pub fn palette(&self) -> &Palette {
+        &self.palettes[self.palette_index]
     }
 
-    pub fn render_frame(&mut self) {
+    fn cycle_palette(&mut self) {
+        self.palette_index = (self.palette_index + 1) % self.palettes.len();
+        println!("Palette {}", self.palette().name);
+    }
+
+    fn toggle_phosphor_fade(&mut self) {
+        if self.phosphor.take().is_some() {
+            println!("Phosphor fade off");
+        } else {
+            println!("Phosphor fade on ({} frames)", self.fade_frames);
+            self.phosphor = Some(PhosphorFade::new(self.fade_frames));
+        }
+    }
+}
+
+impl DisplayBackend for Chip8IO {
+    fn render_frame(&mut self, framebuffer: &Framebuffer) {
+        let width = framebuffer.width();
+        let height = framebuffer.height();
+
+        self.frame_indices.clear();
+        for row in 0..height {
+            for col in 0..width {
+                self.frame_indices.push(framebuffer.color_index(row, col));
+            }
+        }
+        if let Some(frame_blend) = self.frame_blend.as_mut() {
+            frame_blend.apply(&mut self.frame_indices);
+        }
+        let palette = &self.palettes[self.palette_index];
+        self.frame_colors.clear();
+        self.frame_colors
+            .extend(self.frame_indices.iter().map(|&index| palette.color(index)));
+        if let Some(phosphor) = self.phosphor.as_mut() {
+            phosphor.apply(&mut self.frame_colors, palette.background());
+        }
+
+        let (colors, width, height) =
+            if self.filters.is_empty() && self.upscaler == Upscaler::Nearest {
+                (&self.frame_colors, width, height)
+            } else {
+                // Hires frames have twice the pixels in the same window.
+                let scale = (self.scale_factor * LORES_WIDTH / width).max(1);
+                let scale = upscale::upscale(
+                    self.upscaler,
+                    &self.frame_colors,
+                    width,
+                    scale,
+                    &mut self.scaled_colors,
+                );
+                for &filter in &self.filters {
+                    filters::apply(
+                        filter,
+                        &mut self.scaled_colors,
+                        width * scale,
+                        scale,
+                        palette.background(),
+                    );
+                }
+                (&self.scaled_colors, width * scale, height * scale)
+            };
+        self.sdl_mngr.resize_texture(width, height);
+        self.display_buffer.resize(width * height * 4, 0);
+        for (pixel, &color) in self.display_buffer.chunks_exact_mut(4).zip(colors.iter()) {
+            write_color_to_slice(pixel, color);
+        }
+
         self.sdl_mngr
             .texture
             .as_mut()
@@ -216,52 +357,109 @@ impl Chip8IO {
             .expect("Copying texture failed");
         self.sdl_mngr.canvas.present();
     }
+}
 
-    pub fn poll_input(&mut self) -> bool {
+/// Held down to rewind.
+const REWIND_SCANCODE: Scancode = Scancode::Backspace;
+/// Switches phosphor fade on and off. Display settings stay in the backend, they are not `Hotkey`s.
+const FADE_SCANCODE: Scancode = Scancode::F9;
+const PALETTE_SCANCODE: Scancode = Scancode::F10;
+
+/// F1-F4 save to state slots 1-4, F5-F8 load them.
+fn hotkey_for(scancode: Scancode) -> Option<Hotkey> {
+    match scancode {
+        Scancode::F1 => Some(Hotkey::SaveState(1)),
+        Scancode::F2 => Some(Hotkey::SaveState(2)),
+        Scancode::F3 => Some(Hotkey::SaveState(3)),
+        Scancode::F4 => Some(Hotkey::SaveState(4)),
+        Scancode::F5 => Some(Hotkey::LoadState(1)),
+        Scancode::F6 => Some(Hotkey::LoadState(2)),
+        Scancode::F7 => Some(Hotkey::LoadState(3)),
+        Scancode::F8 => Some(Hotkey::LoadState(4)),
+        _ => None,
+    }
+}
+
+impl InputBackend for Chip8IO {
+    fn poll_input(&mut self) -> bool {
         let mut events = self
             .sdl_mngr
             .sdl_context
             .event_pump()
             .expect("Error polling input");
-        loop {
-            for event in events.poll_iter() {
-                match event {
-                    sdl2::event::Event::Quit { .. } => {
-                        return false;
-                    }
-                    sdl2::event::Event::KeyUp { scancode, .. } => {
-                        let key_name = scancode.expect("Missing scancode").name();
-                        if self.keys_pressed.contains_key(key_name) {
-                            self.keys_pressed.insert(key_name, false);
-                        }
+        for event in events.poll_iter() {
+            match event {
+                sdl2::event::Event::Quit { .. } => {
+                    return false;
+                }
+                sdl2::event::Event::KeyUp { scancode, .. } => {
+                    let scancode = scancode.expect("Missing scancode");
+                    let key_name = scancode.name();
+                    if self.keys_pressed.contains_key(key_name) {
+                        self.keys_pressed.insert(key_name, false);
+                    } else if scancode == REWIND_SCANCODE {
+                        self.rewind_held = false;
                     }
-                    sdl2::event::Event::KeyDown { scancode, .. } => {
-                        let key_name = scancode.expect("Missing scancode").name();
-                        if self.keys_pressed.contains_key(key_name) {
-                            self.keys_pressed.insert(key_name, true);
-                        }
+                }
+                sdl2::event::Event::KeyDown {
+                    scancode, repeat, ..
+                } => {
+                    let scancode = scancode.expect("Missing scancode");
+                    let key_name = scancode.name();
+                    if self.keys_pressed.contains_key(key_name) {
+                        self.keys_pressed.insert(key_name, true);
+                    } else if scancode == REWIND_SCANCODE {
+                        self.rewind_held = true;
+                    } else if let Some(hotkey) = hotkey_for(scancode).filter(|_| !repeat) {
+                        self.pending_hotkeys.push(hotkey);
+                    } else if scancode == FADE_SCANCODE && !repeat {
+                        self.toggle_phosphor_fade();
+                    } else if scancode == PALETTE_SCANCODE && !repeat {
+                        self.cycle_palette();
                     }
-                    _ => {}
                 }
+                _ => {}
             }
-            break;
         }
         return true;
     }
 
-    pub fn is_key_pressed(&self, key_num: u8) -> bool {
-        self.keys_pressed[KEYS[key_num as usize]]
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        if self.rewind_held {
+            self.pending_hotkeys.push(Hotkey::Rewind);
+        }
+        std::mem::take(&mut self.pending_hotkeys)
+    }
+
+    fn is_key_pressed(&self, key_num: u8) -> bool {
+        self.keys_pressed[KEYS[(key_num & 0xF) as usize]]
     }
 
-    pub fn play_audio(&self) {
+    fn set_key(&mut self, key_num: u8, pressed: bool) {
+        self.keys_pressed
+            .insert(KEYS[(key_num & 0xF) as usize], pressed);
+    }
+}
+
+impl AudioBackend for Chip8IO {
+    fn play_audio(&self) {
         if let Some(audio_device) = self.sdl_mngr.audio_device.as_ref() {
             audio_device.resume()
         }
     }
 
-    pub fn pause_audio(&self) {
+    fn pause_audio(&self) {
         if let Some(audio_device) = self.sdl_mngr.audio_device.as_ref() {
             audio_device.pause()
         }
     }
+
+    fn set_audio_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], pitch: u8) {
+        if let Some(audio_device) = self.sdl_mngr.audio_device.as_mut() {
+            let mut square_wave = audio_device.lock();
+            let bits_per_second = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+            square_wave.pattern = Some(*pattern);
+            square_wave.pattern_increment = bits_per_second / square_wave.sample_rate;
+        }
+    }
 }
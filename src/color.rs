@@ -0,0 +1,20 @@
+//! Helpers for colors packed as `0xRRGGBBAA`, the format of the palette and display buffers.
+
+pub fn channels(color: u32) -> [u8; 4] {
+    color.to_be_bytes()
+}
+
+pub fn from_channels(channels: [u8; 4]) -> u32 {
+    u32::from_be_bytes(channels)
+}
+
+/// Blends `from` toward `to` by `amount` out of 255, channel by channel.
+pub fn mix(from: u32, to: u32, amount: u8) -> u32 {
+    let (from, to) = (channels(from), channels(to));
+    let amount = amount as u32;
+    let mut mixed = [0; 4];
+    for (channel, (&a, &b)) in mixed.iter_mut().zip(from.iter().zip(to.iter())) {
+        *channel = ((a as u32 * (255 - amount) + b as u32 * amount) / 255) as u8;
+    }
+    from_channels(mixed)
+}
@@ -0,0 +1,354 @@
+//! An interactive terminal debugger.
+//!
+//! The frame loop calls `Debugger::before_cycle` before every instruction and
+//! runs it with `Debugger::run_cycle` instead of `Chip8::run_cycle`. Execution
+//! stops before an instruction when a step count runs out, the PC hits a
+//! breakpoint or the last instruction triggered a break watchpoint. While
+//! stopped it reads commands from stdin, see `HELP`.
+
+use crate::chip8::{self, Chip8, MEMORY_SIZE, NUM_REGISTERS};
+use crate::disasm;
+use crate::watch::{RegisterSnapshot, WatchAction, Watchpoint};
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+Commands (addresses are hex, counts are decimal):
+  s, step [N]          run N instructions, default 1
+  c, continue          run until a breakpoint
+  b, break [ADDR]      set a breakpoint at ADDR, or list breakpoints
+  d, delete ADDR       clear the breakpoint at ADDR
+  w, watch [SPEC]      add a watchpoint like '300-30F w log', 'V3' or 'I', or list them
+  u, unwatch N         remove watchpoint number N
+  r, regs              print registers, I, PC, stack and timers
+  bt, backtrace        print the call stack, innermost call first
+  x, mem ADDR [LEN]    dump LEN bytes of memory from ADDR, default 64
+  l, list [N]          disassemble N instructions from the PC, default 8
+  q, quit              stop the emulator
+  h, help              print this help
+An empty line repeats the last command.";
+
+const DEFAULT_DUMP_LENGTH: usize = 64;
+const DEFAULT_LIST_LENGTH: usize = 8;
+
+/// What the prompt asked for when it returned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resume {
+    Run,
+    Quit,
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>,
+    /// Instructions to run before stopping again, None while continuing.
+    steps_remaining: Option<u64>,
+    last_command: String,
+    quit: bool,
+}
+
+impl Debugger {
+    /// A debugger that stops before the first instruction.
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            steps_remaining: Some(0),
+            last_command: String::new(),
+            quit: false,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Returns false if there was no breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Whether the user asked to stop the emulator.
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Stops the next `run_frame` before its first instruction.
+    pub fn pause(&mut self) {
+        self.steps_remaining = Some(0);
+    }
+
+    /// Runs until a breakpoint or watchpoint instead of stopping before the next instruction.
+    pub fn resume(&mut self) {
+        self.steps_remaining = None;
+    }
+
+    /// Opens the prompt if execution should stop before the next instruction.
+    /// Returns false if the user quit.
+    pub fn before_cycle(&mut self, chip8: &Chip8) -> bool {
+        !(self.should_stop(chip8.pc()) && self.prompt(chip8) == Resume::Quit)
+    }
+
+    /// Runs one instruction and checks the watchpoints against what it did,
+    /// including the accesses of an instruction that failed partway.
+    pub fn run_cycle(&mut self, chip8: &mut Chip8) -> chip8::Result<()> {
+        if self.watchpoints.is_empty() {
+            return chip8.run_cycle();
+        }
+
+        let pc = chip8.pc();
+        let before = RegisterSnapshot::of(chip8);
+        chip8.set_observe_memory(self.watchpoints.iter().any(Watchpoint::watches_memory));
+        let result = chip8.run_cycle();
+        let accesses = chip8.take_memory_accesses();
+        self.check_watchpoints(pc, &before, chip8, &accesses);
+        result
+    }
+
+    /// Prints every watchpoint hit and stops before the next instruction if one of them breaks.
+    fn check_watchpoints(
+        &mut self,
+        pc: usize,
+        before: &RegisterSnapshot,
+        chip8: &Chip8,
+        accesses: &[chip8::MemoryAccess],
+    ) {
+        for watchpoint in &self.watchpoints {
+            for hit in watchpoint.hits(pc, before, chip8, accesses) {
+                println!("Watch {}: {}", watchpoint, hit);
+                if watchpoint.action == WatchAction::Break {
+                    self.steps_remaining = Some(0);
+                }
+            }
+        }
+    }
+
+    /// Opens the prompt after `error` stopped the program, so its final state can be inspected.
+    pub fn post_mortem(&mut self, chip8: &Chip8, error: &chip8::Chip8Error) {
+        println!("Program stopped: {}", error);
+        self.prompt(chip8);
+    }
+
+    fn should_stop(&mut self, pc: usize) -> bool {
+        match self.steps_remaining {
+            Some(0) => return true,
+            Some(steps) => self.steps_remaining = Some(steps - 1),
+            None => {}
+        }
+        if self.breakpoints.contains(&pc) {
+            println!("Breakpoint at 0x{:03X}", pc);
+            return true;
+        }
+        false
+    }
+
+    /// Reads commands until one resumes execution. End of input quits.
+    fn prompt(&mut self, chip8: &Chip8) -> Resume {
+        print_instruction(chip8, chip8.pc());
+        let stdin = std::io::stdin();
+        loop {
+            print!("(chip8) ");
+            std::io::stdout().flush().ok();
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                println!();
+                self.quit = true;
+                return Resume::Quit;
+            }
+            let line = line.trim();
+            if !line.is_empty() {
+                self.last_command = line.to_string();
+            }
+            let command = self.last_command.clone();
+
+            match self.execute(chip8, &command) {
+                Ok(Some(resume)) => {
+                    self.quit = resume == Resume::Quit;
+                    return resume;
+                }
+                Ok(None) => {}
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    /// Runs one command. Returns Some when the command resumes or quits.
+    fn execute(&mut self, chip8: &Chip8, command: &str) -> Result<Option<Resume>, String> {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(None);
+        };
+        let args: Vec<&str> = words.collect();
+
+        match name {
+            "s" | "step" => {
+                let steps = parse_count(args.first(), 1)?;
+                // The current instruction runs right away, so stop after the remaining ones.
+                self.steps_remaining = Some(steps.max(1) as u64 - 1);
+                return Ok(Some(Resume::Run));
+            }
+            "c" | "continue" => {
+                self.steps_remaining = None;
+                return Ok(Some(Resume::Run));
+            }
+            "b" | "break" => match args.first() {
+                Some(arg) => {
+                    let address = parse_address(arg)?;
+                    self.add_breakpoint(address);
+                    println!("Breakpoint set at 0x{:03X}", address);
+                }
+                None if self.breakpoints.is_empty() => println!("No breakpoints"),
+                None => {
+                    for address in self.breakpoints() {
+                        print_instruction(chip8, address);
+                    }
+                }
+            },
+            "d" | "delete" => {
+                let address = parse_address(args.first().ok_or("Usage: delete ADDR")?)?;
+                if !self.remove_breakpoint(address) {
+                    return Err(format!("No breakpoint at 0x{:03X}", address));
+                }
+                println!("Breakpoint cleared at 0x{:03X}", address);
+            }
+            "w" | "watch" if args.is_empty() => {
+                if self.watchpoints.is_empty() {
+                    println!("No watchpoints");
+                }
+                for (number, watchpoint) in self.watchpoints.iter().enumerate() {
+                    println!("{}: {}", number, watchpoint);
+                }
+            }
+            "w" | "watch" => {
+                let watchpoint: Watchpoint = args.join(" ").parse()?;
+                println!("Watchpoint {}: {}", self.watchpoints.len(), watchpoint);
+                self.add_watchpoint(watchpoint);
+            }
+            "u" | "unwatch" => {
+                let number = parse_count(Some(args.first().ok_or("Usage: unwatch N")?), 0)?;
+                if number >= self.watchpoints.len() {
+                    return Err(format!("No watchpoint {}", number));
+                }
+                println!("Removed watchpoint {}", self.watchpoints.remove(number));
+            }
+            "r" | "regs" => print_registers(chip8),
+            "bt" | "backtrace" => print_call_stack(chip8),
+            "x" | "mem" => {
+                let address = parse_address(args.first().ok_or("Usage: mem ADDR [LEN]")?)?;
+                let length = parse_count(args.get(1), DEFAULT_DUMP_LENGTH)?;
+                print_memory(chip8, address, length);
+            }
+            "l" | "list" => {
+                let mut address = chip8.pc();
+                for _ in 0..parse_count(args.first(), DEFAULT_LIST_LENGTH)? {
+                    if address >= MEMORY_SIZE {
+                        break;
+                    }
+                    address += print_instruction(chip8, address);
+                }
+            }
+            "q" | "quit" => return Ok(Some(Resume::Quit)),
+            "h" | "help" => println!("{}", HELP),
+            _ => return Err(format!("Unknown command '{}', try help", name)),
+        }
+        Ok(None)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Prints the instruction at `address` and returns its length.
+fn print_instruction(chip8: &Chip8, address: usize) -> usize {
+    let instruction = disasm::disassemble_at(chip8.memory(), address);
+    println!("{}", disasm::format_line(&instruction));
+    instruction.len().max(1)
+}
+
+fn print_registers(chip8: &Chip8) {
+    for (row, registers) in chip8.registers().chunks(NUM_REGISTERS / 2).enumerate() {
+        let row: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(col, value)| format!("V{:X}={:02X}", row * NUM_REGISTERS / 2 + col, value))
+            .collect();
+        println!("{}", row.join(" "));
+    }
+    println!(
+        "PC={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+        chip8.pc(),
+        chip8.i(),
+        chip8.stack().len(),
+        chip8.delay_timer(),
+        chip8.sound_timer()
+    );
+    let stack: Vec<String> = chip8
+        .stack()
+        .iter()
+        .map(|address| format!("{:04X}", address))
+        .collect();
+    println!("Stack: [{}]", stack.join(" "));
+}
+
+/// Prints the current PC and, for each call on the stack, the `2NNN` that made it and where it returns to.
+fn print_call_stack(chip8: &Chip8) {
+    println!("#0  PC 0x{:03X}", chip8.pc());
+    for (frame, &return_address) in chip8.stack().iter().rev().enumerate() {
+        let call = disasm::disassemble_at(chip8.memory(), return_address.saturating_sub(2));
+        println!(
+            "#{:<2} returns to 0x{:03X}, called from 0x{:03X}: {}",
+            frame + 1,
+            return_address,
+            call.address,
+            call.text
+        );
+    }
+    println!(
+        "Depth {} of {}",
+        chip8.stack().len(),
+        chip8.quirks().stack_depth
+    );
+}
+
+/// Prints 16 bytes per row, stopping at the end of memory.
+fn print_memory(chip8: &Chip8, address: usize, length: usize) {
+    let end = address.saturating_add(length).min(MEMORY_SIZE);
+    for row_start in (address..end).step_by(16) {
+        let bytes: Vec<String> = chip8.memory()[row_start..end.min(row_start + 16)]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        println!("{:04X}  {}", row_start, bytes.join(" "));
+    }
+}
+
+fn parse_address(arg: &str) -> Result<usize, String> {
+    let hex = arg.trim_start_matches("0x").trim_start_matches("0X");
+    match usize::from_str_radix(hex, 16) {
+        Ok(address) if address < MEMORY_SIZE => Ok(address),
+        _ => Err(format!("Invalid address '{}'", arg)),
+    }
+}
+
+fn parse_count(arg: Option<&&str>, default: usize) -> Result<usize, String> {
+    match arg {
+        Some(arg) => arg.parse().map_err(|_| format!("Invalid count '{}'", arg)),
+        None => Ok(default),
+    }
+}
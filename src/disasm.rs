@@ -0,0 +1,211 @@
+//! Turns instructions into mnemonics such as `LD V3, 0x1F` or `DRW V0, V1, 5`.
+//!
+//! Every opcode `Chip8::run_cycle` executes has a mnemonic, and every other
+//! word makes it fail with `InvalidOpcode`, including loose encodings such as
+//! `9XY1` or `E051`. Those are shown as a `db` data directive, so the text of
+//! a listing always assembles back to the same bytes with `asm::assemble`.
+
+use crate::chip8::{LONG_LOAD_OPCODE, Opcode, ROM_START_ADDR};
+
+/// One line of a disassembly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub address: usize,
+    /// The raw bytes, 2 for most instructions, 4 for `F000 NNNN` and 1 for a trailing odd byte.
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl Instruction {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+/// Formats bytes that are not an instruction as a data directive.
+pub fn data_directive(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|&value| byte(value)).collect();
+    format!("db {}", values.join(", "))
+}
+
+/// The mnemonic for a 2 byte instruction, or None if `run_cycle` would not execute it.
+/// `F000` is not handled here because it needs the following word, see `disassemble_at`.
+pub fn disassemble(raw: u16) -> Option<String> {
+    let opcode = Opcode::new(raw);
+    let x = opcode.x();
+    let y = opcode.y();
+    let n = opcode.n();
+    let nn = opcode.get_nn();
+    let nnn = opcode.get_nnn();
+
+    let text = match opcode.op_type() {
+        0x0 => match nnn {
+            0x0E0 => "CLS".to_string(),
+            0x0EE => "RET".to_string(),
+            0x0C0..=0x0CF => format!("SCD {}", n),
+            0x0D0..=0x0DF => format!("SCU {}", n),
+            0x0FB => "SCR".to_string(),
+            0x0FC => "SCL".to_string(),
+            0x0FD => "EXIT".to_string(),
+            0x0FE => "LOW".to_string(),
+            0x0FF => "HIGH".to_string(),
+            _ => format!("SYS {}", addr(nnn)),
+        },
+        0x1 => format!("JP {}", addr(nnn)),
+        0x2 => format!("CALL {}", addr(nnn)),
+        0x3 => format!("SE V{:X}, {}", x, byte(nn)),
+        0x4 => format!("SNE V{:X}, {}", x, byte(nn)),
+        0x5 => match n {
+            0x0 => format!("SE V{:X}, V{:X}", x, y),
+            0x2 => format!("SAVE V{:X}, V{:X}", x, y),
+            0x3 => format!("LOAD V{:X}, V{:X}", x, y),
+            _ => return None,
+        },
+        0x6 => format!("LD V{:X}, {}", x, byte(nn)),
+        0x7 => format!("ADD V{:X}, {}", x, byte(nn)),
+        0x8 => {
+            let mnemonic = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return None,
+            };
+            format!("{} V{:X}, V{:X}", mnemonic, x, y)
+        }
+        0x9 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA => format!("LD I, {}", addr(nnn)),
+        0xB => format!("JP V0, {}", addr(nnn)),
+        0xC => format!("RND V{:X}, {}", x, byte(nn)),
+        0xD => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE => match nn {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => return None,
+        },
+        0xF => match nn {
+            0x01 => format!("PLANE {}", x),
+            0x02 if x == 0 => "AUDIO".to_string(),
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x3A => format!("PITCH V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            0x75 => format!("LD R, V{:X}", x),
+            0x85 => format!("LD V{:X}, R", x),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Disassembles the instruction at `address`, reading the second word of `F000 NNNN`.
+/// Bytes that are not an instruction come back as a `db` directive.
+pub fn disassemble_at(memory: &[u8], address: usize) -> Instruction {
+    let remaining = &memory[address.min(memory.len())..];
+    if remaining.len() < 2 {
+        return Instruction {
+            address,
+            bytes: remaining.to_vec(),
+            text: data_directive(remaining),
+        };
+    }
+
+    let raw = u16::from_be_bytes([remaining[0], remaining[1]]);
+    if raw == LONG_LOAD_OPCODE && remaining.len() >= 4 {
+        let long_address = u16::from_be_bytes([remaining[2], remaining[3]]);
+        return Instruction {
+            address,
+            bytes: remaining[..4].to_vec(),
+            text: format!("LD I, LONG 0x{:04X}", long_address),
+        };
+    }
+
+    Instruction {
+        address,
+        bytes: remaining[..2].to_vec(),
+        text: disassemble(raw).unwrap_or_else(|| data_directive(&remaining[..2])),
+    }
+}
+
+/// Disassembles a whole ROM front to back, as loaded at `ROM_START_ADDR`.
+pub fn disassemble_rom(rom: &[u8]) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let mut instruction = disassemble_at(rom, offset);
+        offset += instruction.len();
+        instruction.address += ROM_START_ADDR;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/// Formats one listing line: address, raw bytes and mnemonic.
+pub fn format_line(instruction: &Instruction) -> String {
+    let bytes: Vec<String> = instruction
+        .bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    format!(
+        "{:04X}  {:<12} {}",
+        instruction.address,
+        bytes.join(" "),
+        instruction.text
+    )
+}
+
+fn addr(nnn: u16) -> String {
+    format!("0x{:03X}", nnn)
+}
+
+fn byte(nn: u8) -> String {
+    format!("0x{:02X}", nn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{Chip8, Chip8Error};
+    use crate::headless::HeadlessIO;
+    use crate::machine_code::MachineCodePolicy;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn mnemonics_match_the_opcodes_run_cycle_executes() {
+        let io = Rc::new(RefCell::new(HeadlessIO::new()));
+        for raw in 0..=u16::MAX {
+            let mut chip8 = Chip8::new(&io);
+            chip8.set_machine_code_policy(MachineCodePolicy::Ignore);
+            let [high, low] = raw.to_be_bytes();
+            chip8.load_rom_bytes(&[high, low, 0x12, 0x34]).unwrap();
+            let executed = !matches!(chip8.run_cycle(), Err(Chip8Error::InvalidOpcode(_)));
+            let text = disassemble_at(&[high, low, 0x12, 0x34], 0).text;
+            assert_eq!(executed, !text.starts_with("db"), "{:04X}: {}", raw, text);
+        }
+    }
+
+    #[test]
+    fn loose_skip_encodings_are_data() {
+        for raw in [0x9121, 0x912F, 0xE051, 0xE03E] {
+            assert_eq!(disassemble(raw), None, "{:04X}", raw);
+        }
+    }
+}
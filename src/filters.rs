@@ -0,0 +1,166 @@
+//! CRT and LCD display filters, computed on the CPU.
+//!
+//! Filters work on the scaled image, in which every emulated pixel is a
+//! `scale` x `scale` block, so they need no GPU and look the same everywhere.
+//! Several filters can be combined, they are applied in the order given.
+
+use crate::color;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Darkens the bottom rows of every pixel row, like the gaps between CRT scanlines.
+    Scanlines,
+    /// Fades the right column and bottom row of every pixel toward the background, like LCD dots.
+    Grid,
+    /// Lets lit pixels glow onto their surroundings.
+    Bloom,
+    /// Rounds the corners of every pixel off into the background.
+    Rounded,
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "scanlines" => Ok(Filter::Scanlines),
+            "grid" | "lcd" => Ok(Filter::Grid),
+            "bloom" => Ok(Filter::Bloom),
+            "rounded" => Ok(Filter::Rounded),
+            _ => Err(format!(
+                "Unknown filter '{}': expected scanlines, grid, bloom or rounded",
+                s
+            )),
+        }
+    }
+}
+
+/// How far scanlines are darkened toward black, out of 255.
+const SCANLINE_DARKNESS: u8 = 110;
+/// How far grid lines fade toward the background, out of 255.
+const GRID_FADE: u8 = 140;
+/// How much of the blurred image bloom mixes in, out of 255.
+const BLOOM_STRENGTH: u8 = 200;
+
+/// Applies `filter` to `pixels`, an image `width` pixels wide made of `scale` x `scale`
+/// blocks, one per emulated pixel. `background` is the palette's background color.
+pub fn apply(filter: Filter, pixels: &mut [u32], width: usize, scale: usize, background: u32) {
+    if width == 0 || scale == 0 {
+        return;
+    }
+    match filter {
+        Filter::Scanlines => scanlines(pixels, width, scale),
+        Filter::Grid => grid(pixels, width, scale, background),
+        Filter::Bloom => bloom(pixels, width, scale),
+        Filter::Rounded => rounded(pixels, width, scale, background),
+    }
+}
+
+/// Black with the alpha of `color`, so darkening keeps transparency.
+fn black_like(color: u32) -> u32 {
+    color & 0xFF
+}
+
+fn scanlines(pixels: &mut [u32], width: usize, scale: usize) {
+    if scale < 2 {
+        return;
+    }
+    let dark_rows = (scale / 4).max(1);
+    for (y, row) in pixels.chunks_exact_mut(width).enumerate() {
+        if y % scale >= scale - dark_rows {
+            for pixel in row {
+                *pixel = color::mix(*pixel, black_like(*pixel), SCANLINE_DARKNESS);
+            }
+        }
+    }
+}
+
+fn grid(pixels: &mut [u32], width: usize, scale: usize, background: u32) {
+    if scale < 3 {
+        return;
+    }
+    for (y, row) in pixels.chunks_exact_mut(width).enumerate() {
+        let line_row = y % scale == scale - 1;
+        for (x, pixel) in row.iter_mut().enumerate() {
+            if line_row || x % scale == scale - 1 {
+                *pixel = color::mix(*pixel, background, GRID_FADE);
+            }
+        }
+    }
+}
+
+/// Box blurs the image over about one emulated pixel and keeps whichever of
+/// the original and the blur is brighter, so only the surroundings of lit pixels change.
+fn bloom(pixels: &mut [u32], width: usize, scale: usize) {
+    let height = pixels.len() / width;
+    let radius = (scale / 2).max(1);
+    let blurred = box_blur(
+        &box_blur(pixels, width, height, radius, true),
+        width,
+        height,
+        radius,
+        false,
+    );
+    for (pixel, &glow) in pixels.iter_mut().zip(blurred.iter()) {
+        let glow = color::mix(black_like(glow), glow, BLOOM_STRENGTH);
+        let (old, glow) = (color::channels(*pixel), color::channels(glow));
+        *pixel = color::from_channels([
+            old[0].max(glow[0]),
+            old[1].max(glow[1]),
+            old[2].max(glow[2]),
+            old[3],
+        ]);
+    }
+}
+
+/// Averages every pixel with the `radius` pixels on each side, along rows if
+/// `horizontal` or else along columns. Running sums keep the cost independent of the radius.
+fn box_blur(
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+    radius: usize,
+    horizontal: bool,
+) -> Vec<u32> {
+    let mut blurred = vec![0; pixels.len()];
+    // Each line is `line_len` pixels, `step` apart, starting `line_step` after the previous line.
+    let (lines, line_len, line_step, step) = if horizontal {
+        (height, width, width, 1)
+    } else {
+        (width, height, 1, width)
+    };
+    let mut sums = vec![[0u32; 4]; line_len + 1];
+    for line in 0..lines {
+        let first = line * line_step;
+        for i in 0..line_len {
+            let channels = color::channels(pixels[first + i * step]);
+            sums[i + 1] = std::array::from_fn(|c| sums[i][c] + channels[c] as u32);
+        }
+        for i in 0..line_len {
+            let (start, end) = (i.saturating_sub(radius), (i + radius + 1).min(line_len));
+            let count = (end - start) as u32;
+            let average = std::array::from_fn(|c| ((sums[end][c] - sums[start][c]) / count) as u8);
+            blurred[first + i * step] = color::from_channels(average);
+        }
+    }
+    blurred
+}
+
+fn rounded(pixels: &mut [u32], width: usize, scale: usize, background: u32) {
+    if scale < 4 {
+        return;
+    }
+    // Corners are cut outside a quarter circle of this radius, in block pixels.
+    let radius = scale / 3;
+    for (y, row) in pixels.chunks_exact_mut(width).enumerate() {
+        let by = y % scale;
+        let cy = radius.saturating_sub(by.min(scale - 1 - by));
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let bx = x % scale;
+            let cx = radius.saturating_sub(bx.min(scale - 1 - bx));
+            if cx * cx + cy * cy > radius * radius {
+                *pixel = background;
+            }
+        }
+    }
+}
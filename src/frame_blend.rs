@@ -0,0 +1,118 @@
+//! Frame blending: another way to hide XOR flicker, chosen with `--frame-blend`.
+//!
+//! * `or:N` shows a pixel lit if it was lit in any of the last N emulated
+//!   frames, so a sprite erased in one frame and redrawn in the next never
+//!   disappears. Plain `or` blends 2 frames.
+//! * `settle` holds the last presented frame while the program is only
+//!   erasing, and presents once new pixels are drawn or the screen stops
+//!   changing, at most `MAX_HOLD_FRAMES` frames later.
+//!
+//! Both work on color indices, before the palette is applied.
+
+use std::collections::VecDeque;
+
+/// The longest `settle` waits for drawing to finish, so erasing the screen still shows up.
+pub const MAX_HOLD_FRAMES: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameBlendMode {
+    /// OR of the last N frames.
+    Or(usize),
+    Settle,
+}
+
+impl std::str::FromStr for FrameBlendMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        match s.split_once(':') {
+            None if s == "or" => Ok(FrameBlendMode::Or(2)),
+            None if s == "settle" => Ok(FrameBlendMode::Settle),
+            Some(("or", frames)) => match frames.parse() {
+                Ok(frames) if frames > 0 => Ok(FrameBlendMode::Or(frames)),
+                _ => Err(format!("Invalid frame count '{}'", frames)),
+            },
+            _ => Err(format!(
+                "Unknown frame blend mode '{}': expected or, or:N or settle",
+                s
+            )),
+        }
+    }
+}
+
+pub struct FrameBlend {
+    mode: FrameBlendMode,
+    /// The most recent emulated frames, newest last.
+    history: VecDeque<Vec<u8>>,
+    /// The frame `settle` presented last.
+    shown: Vec<u8>,
+    held_frames: u32,
+}
+
+impl FrameBlend {
+    pub fn new(mode: FrameBlendMode) -> Self {
+        Self {
+            mode,
+            history: VecDeque::new(),
+            shown: Vec::new(),
+            held_frames: 0,
+        }
+    }
+
+    pub fn mode(&self) -> FrameBlendMode {
+        self.mode
+    }
+
+    /// Takes the color indices of the newest emulated frame and replaces them with the ones to show.
+    pub fn apply(&mut self, indices: &mut [u8]) {
+        // After a resolution change older frames no longer line up.
+        if self
+            .history
+            .back()
+            .is_some_and(|frame| frame.len() != indices.len())
+        {
+            self.history.clear();
+            self.shown.clear();
+        }
+        let keep = match self.mode {
+            FrameBlendMode::Or(frames) => frames,
+            FrameBlendMode::Settle => 1,
+        };
+        let previous = if self.history.len() >= keep {
+            self.history.pop_front()
+        } else {
+            None
+        };
+        self.history.push_back(indices.to_vec());
+
+        match self.mode {
+            FrameBlendMode::Or(_) => {
+                for frame in self.history.iter().rev().skip(1) {
+                    for (index, &old) in indices.iter_mut().zip(frame.iter()) {
+                        *index |= old;
+                    }
+                }
+            }
+            FrameBlendMode::Settle => {
+                let only_erasing = previous.is_some_and(|previous| {
+                    let mut erased = false;
+                    for (&new, &old) in indices.iter().zip(previous.iter()) {
+                        if new & !old != 0 {
+                            return false;
+                        }
+                        erased |= old & !new != 0;
+                    }
+                    erased
+                });
+                if only_erasing && self.held_frames < MAX_HOLD_FRAMES && !self.shown.is_empty() {
+                    self.held_frames += 1;
+                    indices.copy_from_slice(&self.shown);
+                } else {
+                    self.held_frames = 0;
+                    self.shown = indices.to_vec();
+                }
+            }
+        }
+    }
+}
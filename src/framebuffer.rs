@@ -0,0 +1,195 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+/// XO-CHIP draws on two bit planes, giving four colors per pixel.
+pub const NUM_PLANES: usize = 2;
+/// The number of distinct values `Framebuffer::color_index` can return.
+pub const NUM_COLORS: usize = 1 << NUM_PLANES;
+
+type Plane = [u128; HIRES_HEIGHT];
+
+/// The CHIP-8 screen, either 64x32 (low resolution) or 128x64 (SUPER-CHIP
+/// high resolution), with one bit per pixel in each of `NUM_PLANES` planes.
+/// Each row of a plane is a bit mask with column 0 in the most significant bit.
+///
+/// Clearing and scrolling only affect the selected planes, which is plane 0
+/// alone unless an XO-CHIP program selects others.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    planes: [Plane; NUM_PLANES],
+    selected_planes: u8,
+    hires: bool,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            planes: [[0; HIRES_HEIGHT]; NUM_PLANES],
+            selected_planes: 0b01,
+            hires: false,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switches between 64x32 and 128x64. Every plane is cleared.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.planes = [[0; HIRES_HEIGHT]; NUM_PLANES];
+    }
+
+    /// The selected planes as a bit mask, bit 0 for plane 0.
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected_planes = mask & ((1 << NUM_PLANES) - 1);
+    }
+
+    pub fn clear(&mut self) {
+        for plane in self.selected_planes_mut() {
+            plane.fill(0);
+        }
+    }
+
+    /// Whether the pixel at `row`, `col` is lit in any plane.
+    pub fn get(&self, row: usize, col: usize) -> bool {
+        self.color_index(row, col) != 0
+    }
+
+    /// The pixel's bits across all planes, bit 0 for plane 0. Used to pick a palette entry.
+    pub fn color_index(&self, row: usize, col: usize) -> u8 {
+        let mask = Self::mask(col);
+        self.planes
+            .iter()
+            .enumerate()
+            .fold(0, |index, (plane_num, plane)| {
+                index | (((plane[row] & mask != 0) as u8) << plane_num)
+            })
+    }
+
+    /// Flips the pixel at `row`, `col` in `plane`. Returns true if it was lit, i.e. the flip is a collision.
+    pub fn toggle(&mut self, plane: usize, row: usize, col: usize) -> bool {
+        let mask = Self::mask(col);
+        let collision = self.planes[plane][row] & mask != 0;
+        self.planes[plane][row] ^= mask;
+        collision
+    }
+
+    /// Moves the picture down by `n` rows, blanking the rows scrolled in at the top.
+    pub fn scroll_down(&mut self, n: usize) {
+        let height = self.height();
+        let n = n.min(height);
+        for plane in self.selected_planes_mut() {
+            plane.copy_within(0..height - n, n);
+            plane[..n].fill(0);
+        }
+    }
+
+    /// Moves the picture up by `n` rows, blanking the rows scrolled in at the bottom.
+    pub fn scroll_up(&mut self, n: usize) {
+        let height = self.height();
+        let n = n.min(height);
+        for plane in self.selected_planes_mut() {
+            plane.copy_within(n..height, 0);
+            plane[height - n..height].fill(0);
+        }
+    }
+
+    /// Moves the picture right by `n` columns, dropping the columns pushed past the edge.
+    pub fn scroll_right(&mut self, n: usize) {
+        let visible = Self::visible_mask(self.width());
+        for plane in self.selected_planes_mut() {
+            for row in plane.iter_mut() {
+                *row = row.checked_shr(n as u32).unwrap_or(0) & visible;
+            }
+        }
+    }
+
+    /// Moves the picture left by `n` columns, blanking the columns scrolled in on the right.
+    pub fn scroll_left(&mut self, n: usize) {
+        for plane in self.selected_planes_mut() {
+            for row in plane.iter_mut() {
+                *row = row.checked_shl(n as u32).unwrap_or(0);
+            }
+        }
+    }
+
+    pub(crate) fn write_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.hires);
+        writer.write_u8(self.selected_planes);
+        for plane in &self.planes {
+            for &row in plane {
+                writer.write_u128(row);
+            }
+        }
+    }
+
+    pub(crate) fn read_state(reader: &mut StateReader) -> Result<Self, SaveStateError> {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.hires = reader.read_bool()?;
+        framebuffer.selected_planes = reader.read_u8()?;
+        for plane in framebuffer.planes.iter_mut() {
+            for row in plane.iter_mut() {
+                *row = reader.read_u128()?;
+            }
+        }
+        Ok(framebuffer)
+    }
+
+    fn selected_planes_mut(&mut self) -> impl Iterator<Item = &mut Plane> {
+        let selected_planes = self.selected_planes;
+        self.planes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(plane_num, _)| selected_planes & (1 << plane_num) != 0)
+            .map(|(_, plane)| plane)
+    }
+
+    fn mask(col: usize) -> u128 {
+        1 << (HIRES_WIDTH - 1 - col)
+    }
+
+    /// The bits of a row that hold the first `width` columns.
+    fn visible_mask(width: usize) -> u128 {
+        !u128::MAX.checked_shr(width as u32).unwrap_or(0)
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for Framebuffer {
+    /// One line per row: `.` for unlit pixels, `#` for plane 0, `+` for plane 1 and `@` for both.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const CHARS: [char; 4] = ['.', '#', '+', '@'];
+        for row in 0..self.height() {
+            for col in 0..self.width() {
+                write!(f, "{}", CHARS[self.color_index(row, col) as usize])?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
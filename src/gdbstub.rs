@@ -0,0 +1,430 @@
+//! A GDB remote serial protocol stub, so gdb front-ends and scripts can drive the machine.
+//!
+//! The stub listens on a localhost TCP port and serves one client. Registers
+//! are numbered as below, each sent in target byte order (little endian):
+//!
+//! | Number | Register | Size |
+//! |--------|----------|------|
+//! | 0-15   | V0-VF    | 1    |
+//! | 16     | I        | 2    |
+//! | 17     | PC       | 2    |
+//! | 18     | SP       | 1    |
+//! | 19     | DT       | 1    |
+//! | 20     | ST       | 1    |
+//!
+//! Supported packets: `?`, `g`, `G`, `p`, `P`, `m`, `M`, `s`, `c`, `Z0`/`z0`
+//! software breakpoints, `D`, `k` and `qXfer:features:read` for the
+//! `target.xml` describing the registers above. A `0x03` byte interrupts a
+//! running program. Writing SP can only drop return addresses, not push new ones.
+
+use crate::chip8::{self, Chip8, MEMORY_SIZE, NUM_REGISTERS};
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const INTERRUPT: u8 = 0x03;
+
+const REG_I: usize = NUM_REGISTERS;
+const REG_PC: usize = NUM_REGISTERS + 1;
+const REG_SP: usize = NUM_REGISTERS + 2;
+const REG_DT: usize = NUM_REGISTERS + 3;
+const REG_ST: usize = NUM_REGISTERS + 4;
+const NUM_GDB_REGISTERS: usize = NUM_REGISTERS + 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunState {
+    Halted,
+    Stepping,
+    Running,
+}
+
+pub struct GdbStub {
+    stream: TcpStream,
+    state: RunState,
+    breakpoints: BTreeSet<usize>,
+    detached: bool,
+    killed: bool,
+}
+
+impl GdbStub {
+    /// Waits for a client on 127.0.0.1:`port`. The machine starts halted.
+    pub fn listen(port: u16) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("Waiting for gdb on 127.0.0.1:{}", port);
+        let (stream, address) = listener.accept()?;
+        println!("gdb connected from {}", address);
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            state: RunState::Halted,
+            breakpoints: BTreeSet::new(),
+            detached: false,
+            killed: false,
+        })
+    }
+
+    /// Whether the client killed the program with `k`.
+    pub fn has_quit(&self) -> bool {
+        self.killed
+    }
+
+    /// Stops at breakpoints and interrupts, then serves the client until it resumes.
+    /// Returns false if the client killed the program.
+    pub fn before_cycle(&mut self, chip8: &mut Chip8) -> bool {
+        if self.detached {
+            return !self.killed;
+        }
+        // Checked before serving, so the instruction the client resumes at runs even if it has a breakpoint.
+        if self.state == RunState::Running
+            && (self.poll_interrupt() || self.breakpoints.contains(&chip8.pc()))
+        {
+            self.halt(SIGTRAP);
+        }
+        if self.state == RunState::Halted {
+            self.serve(chip8);
+        }
+        !self.killed
+    }
+
+    /// Reports the end of a single step, an error or the program exiting to the client.
+    pub fn after_cycle(&mut self, chip8: &mut Chip8, result: &chip8::Result<()>) {
+        if self.detached {
+            return;
+        }
+        if let Err(e) = result {
+            println!("{}", e);
+            self.halt(SIGILL);
+            // The machine cannot continue, but the client can still inspect it.
+            self.serve(chip8);
+        } else if chip8.has_exited() {
+            // The session is over for the client, and `run_cycle` keeps succeeding
+            // until the frame ends, so the exit is reported once and the stub stops.
+            self.send_packet("W00");
+            self.detached = true;
+        } else if self.state == RunState::Stepping {
+            self.halt(SIGTRAP);
+        }
+    }
+
+    fn halt(&mut self, signal: u8) {
+        self.state = RunState::Halted;
+        self.send_packet(&format!("S{:02x}", signal));
+    }
+
+    /// Handles packets until the client resumes, detaches or disconnects.
+    fn serve(&mut self, chip8: &mut Chip8) {
+        while self.state == RunState::Halted && !self.detached {
+            let Some(packet) = self.read_packet() else {
+                println!("gdb disconnected");
+                self.detached = true;
+                self.state = RunState::Running;
+                return;
+            };
+            let reply = self.handle_packet(chip8, &packet);
+            if let Some(reply) = reply {
+                self.send_packet(&reply);
+            }
+        }
+    }
+
+    /// Returns the reply to send, None for packets that resume without one.
+    fn handle_packet(&mut self, chip8: &mut Chip8, packet: &str) -> Option<String> {
+        let command = packet.get(..1).unwrap_or_default();
+        let args = packet.get(1..).unwrap_or_default();
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..NUM_GDB_REGISTERS)
+                .map(|register| encode_hex(&read_register(chip8, register)))
+                .collect(),
+            "G" => match decode_hex(args) {
+                Some(bytes) => {
+                    let mut offset = 0;
+                    for register in 0..NUM_GDB_REGISTERS {
+                        let size = register_size(register);
+                        if let Some(value) = bytes.get(offset..offset + size) {
+                            write_register(chip8, register, value);
+                        }
+                        offset += size;
+                    }
+                    "OK".to_string()
+                }
+                None => error_reply(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(register) if register < NUM_GDB_REGISTERS => {
+                    encode_hex(&read_register(chip8, register))
+                }
+                _ => error_reply(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(register, value)| {
+                    Some((
+                        usize::from_str_radix(register, 16).ok()?,
+                        decode_hex(value)?,
+                    ))
+                });
+                match parsed {
+                    Some((register, value)) if register < NUM_GDB_REGISTERS => {
+                        if write_register(chip8, register, &value) {
+                            "OK".to_string()
+                        } else {
+                            error_reply()
+                        }
+                    }
+                    _ => error_reply(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((address, len)) => encode_hex(&chip8.memory()[address..address + len]),
+                None => error_reply(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    let (address, len) = parse_range(range)?;
+                    let data = decode_hex(data)?;
+                    (data.len() == len).then_some((address, data))
+                });
+                match parsed {
+                    Some((address, data)) => {
+                        chip8.write_memory(address, &data);
+                        "OK".to_string()
+                    }
+                    None => error_reply(),
+                }
+            }
+            "s" | "c" => {
+                if let Ok(address) = usize::from_str_radix(args, 16) {
+                    chip8.set_pc(address);
+                }
+                self.state = if command == "s" {
+                    RunState::Stepping
+                } else {
+                    RunState::Running
+                };
+                return None;
+            }
+            "Z" | "z" => match parse_breakpoint(args) {
+                Some(address) => {
+                    if command == "Z" {
+                        self.breakpoints.insert(address);
+                    } else {
+                        self.breakpoints.remove(&address);
+                    }
+                    "OK".to_string()
+                }
+                // Only software breakpoints are supported, an empty reply tells gdb so.
+                None => String::new(),
+            },
+            "D" => {
+                self.detached = true;
+                self.state = RunState::Running;
+                "OK".to_string()
+            }
+            "k" => {
+                self.detached = true;
+                self.killed = true;
+                return None;
+            }
+            "H" => "OK".to_string(),
+            _ if packet == "qAttached" => "1".to_string(),
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=4000;qXfer:features:read+".to_string()
+            }
+            _ if packet.starts_with("qXfer:features:read:") => {
+                let args = &packet["qXfer:features:read:".len()..];
+                match args.split_once(':') {
+                    Some(("target.xml", range)) => match parse_offset_length(range) {
+                        Some((offset, len)) => read_chunk(&target_description(), offset, len),
+                        None => error_reply(),
+                    },
+                    // Unknown annex.
+                    _ => "E00".to_string(),
+                }
+            }
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// Reads the next `$data#checksum` packet and acknowledges it. None if the connection closed.
+    fn read_packet(&mut self) -> Option<String> {
+        loop {
+            match self.read_byte()? {
+                b'$' => break,
+                // Acks and interrupts while halted need no answer.
+                _ => continue,
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte()? {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte()?, self.read_byte()?];
+        let expected = format!("{:02x}", checksum_of(&data));
+        if !checksum.eq_ignore_ascii_case(expected.as_bytes()) {
+            self.stream.write_all(b"-").ok()?;
+            return self.read_packet();
+        }
+        self.stream.write_all(b"+").ok()?;
+        Some(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        if self.stream.write_all(packet.as_bytes()).is_err() {
+            println!("gdb disconnected");
+            self.detached = true;
+            self.state = RunState::Running;
+        }
+    }
+
+    /// Checks, without blocking, whether the client sent an interrupt.
+    fn poll_interrupt(&mut self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut byte = [0];
+        let interrupted = matches!(self.stream.read(&mut byte), Ok(1) if byte[0] == INTERRUPT);
+        self.stream.set_nonblocking(false).ok();
+        interrupted
+    }
+}
+
+fn register_size(register: usize) -> usize {
+    match register {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+fn read_register(chip8: &Chip8, register: usize) -> Vec<u8> {
+    match register {
+        REG_I => (chip8.i() as u16).to_le_bytes().to_vec(),
+        REG_PC => (chip8.pc() as u16).to_le_bytes().to_vec(),
+        REG_SP => vec![chip8.stack().len() as u8],
+        REG_DT => vec![chip8.delay_timer()],
+        REG_ST => vec![chip8.sound_timer()],
+        _ => vec![chip8.registers()[register]],
+    }
+}
+
+/// Returns false if the value has the wrong size or SP would grow.
+fn write_register(chip8: &mut Chip8, register: usize, value: &[u8]) -> bool {
+    if value.len() != register_size(register) {
+        return false;
+    }
+    match register {
+        REG_I => chip8.set_i(u16::from_le_bytes([value[0], value[1]]) as usize),
+        REG_PC => chip8.set_pc(u16::from_le_bytes([value[0], value[1]]) as usize),
+        REG_SP => {
+            if value[0] as usize > chip8.stack().len() {
+                return false;
+            }
+            chip8.truncate_stack(value[0] as usize);
+        }
+        REG_DT => chip8.set_delay_timer(value[0]),
+        REG_ST => chip8.set_sound_timer(value[0]),
+        _ => chip8.set_register(register, value[0]),
+    }
+    true
+}
+
+/// Parses `addr,length` in hex, rejecting ranges outside memory.
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let (address, len) = args.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    (address.checked_add(len)? <= MEMORY_SIZE).then_some((address, len))
+}
+
+/// Parses `offset,length` in hex, as sent in `qXfer` reads.
+fn parse_offset_length(args: &str) -> Option<(usize, usize)> {
+    let (offset, len) = args.split_once(',')?;
+    Some((
+        usize::from_str_radix(offset, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// The `qXfer` reply for `len` bytes of `data` at `offset`: `m` and the bytes
+/// if more follow, `l` and the bytes if they are the last.
+fn read_chunk(data: &str, offset: usize, len: usize) -> String {
+    let start = offset.min(data.len());
+    let end = offset.saturating_add(len).min(data.len());
+    let prefix = if end < data.len() { 'm' } else { 'l' };
+    format!("{}{}", prefix, &data[start..end])
+}
+
+/// The gdb target description of the registers, numbered as in the module docs.
+/// It contains none of the characters `#$}*` that would need escaping in a reply.
+fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n\
+         <feature name=\"org.chip8.core\">\n",
+    );
+    for register in 0..NUM_GDB_REGISTERS {
+        let (name, kind) = match register {
+            REG_I => ("i".to_string(), "data_ptr"),
+            REG_PC => ("pc".to_string(), "code_ptr"),
+            REG_SP => ("sp".to_string(), "uint8"),
+            REG_DT => ("dt".to_string(), "uint8"),
+            REG_ST => ("st".to_string(), "uint8"),
+            _ => (format!("v{:x}", register), "uint8"),
+        };
+        xml.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>\n",
+            name,
+            register_size(register) * 8,
+            kind,
+            register
+        ));
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+/// Parses `0,addr,kind` for a software breakpoint.
+fn parse_breakpoint(args: &str) -> Option<usize> {
+    let mut fields = args.split(',');
+    if fields.next()? != "0" {
+        return None;
+    }
+    usize::from_str_radix(fields.next()?, 16).ok()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|pos| u8::from_str_radix(hex.get(pos..pos + 2)?, 16).ok())
+        .collect()
+}
+
+fn error_reply() -> String {
+    "E01".to_string()
+}
@@ -0,0 +1,190 @@
+use crate::backend::{AudioBackend, DisplayBackend, InputBackend, NUM_KEYS};
+use crate::chip8::AUDIO_PATTERN_SIZE;
+use crate::framebuffer::Framebuffer;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+/// A scripted key change, applied when `poll_input` reaches `frame`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Parses a key script made of `frame:key:down` or `frame:key:up` entries.
+/// Entries are separated by commas or newlines, keys are hex digits and
+/// lines starting with `#` are ignored.
+pub fn parse_key_script(script: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut events = Vec::new();
+
+    for entry in script
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let parts: Vec<&str> = entry.split(':').collect();
+        if parts.len() != 3 {
+            return Err(format!(
+                "Invalid key event '{}': expected frame:key:down|up",
+                entry
+            ));
+        }
+
+        let frame = parts[0]
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid frame in '{}': {}", entry, e))?;
+        let key = u8::from_str_radix(parts[1], 16)
+            .ok()
+            .filter(|&key| (key as usize) < NUM_KEYS)
+            .ok_or(format!("Invalid key in '{}': expected 0-F", entry))?;
+        let pressed = match parts[2] {
+            "down" => true,
+            "up" => false,
+            other => {
+                return Err(format!(
+                    "Invalid key state '{}': expected down or up",
+                    other
+                ));
+            }
+        };
+
+        events.push(KeyEvent {
+            frame,
+            key,
+            pressed,
+        });
+    }
+
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+/// A backend without a window or audio device. The last rendered frame is kept in memory,
+/// keys come from a script and sound timer activity is recorded per frame.
+pub struct HeadlessIO {
+    last_frame: Framebuffer,
+    keys_pressed: [bool; NUM_KEYS],
+    key_script: VecDeque<KeyEvent>,
+    frame: u64,
+    max_frames: Option<u64>,
+    audio_playing: Cell<bool>,
+    sound_frames: Cell<u64>,
+    sound_starts: RefCell<Vec<u64>>,
+    audio_pattern: Option<([u8; AUDIO_PATTERN_SIZE], u8)>,
+}
+
+impl HeadlessIO {
+    pub fn new() -> Self {
+        Self {
+            last_frame: Framebuffer::new(),
+            keys_pressed: [false; NUM_KEYS],
+            key_script: VecDeque::new(),
+            frame: 0,
+            max_frames: None,
+            audio_playing: Cell::new(false),
+            sound_frames: Cell::new(0),
+            sound_starts: RefCell::new(Vec::new()),
+            audio_pattern: None,
+        }
+    }
+
+    /// Replaces the pending key script. Events must be sorted by frame.
+    pub fn set_key_script(&mut self, events: Vec<KeyEvent>) {
+        self.key_script = events.into();
+    }
+
+    /// Makes `poll_input` report a quit request once `frames` frames have run.
+    pub fn set_max_frames(&mut self, frames: Option<u64>) {
+        self.max_frames = frames;
+    }
+
+    /// The number of frames polled so far. Frames in key scripts and sound starts count from 0.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The number of frames during which the sound timer was active.
+    pub fn sound_frames(&self) -> u64 {
+        self.sound_frames.get()
+    }
+
+    /// The frames at which the sound timer went from silent to active.
+    pub fn sound_starts(&self) -> Vec<u64> {
+        self.sound_starts.borrow().clone()
+    }
+
+    /// The last XO-CHIP audio pattern and pitch, if the program set one.
+    pub fn audio_pattern(&self) -> Option<([u8; AUDIO_PATTERN_SIZE], u8)> {
+        self.audio_pattern
+    }
+
+    /// The framebuffer passed to the most recent `render_frame` call.
+    pub fn last_frame(&self) -> &Framebuffer {
+        &self.last_frame
+    }
+}
+
+impl Default for HeadlessIO {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DisplayBackend for HeadlessIO {
+    fn render_frame(&mut self, framebuffer: &Framebuffer) {
+        self.last_frame.clone_from(framebuffer);
+    }
+}
+
+impl InputBackend for HeadlessIO {
+    fn poll_input(&mut self) -> bool {
+        if self
+            .max_frames
+            .is_some_and(|max_frames| self.frame >= max_frames)
+        {
+            return false;
+        }
+
+        while let Some(event) = self.key_script.front() {
+            if event.frame > self.frame {
+                break;
+            }
+            self.keys_pressed[event.key as usize] = event.pressed;
+            self.key_script.pop_front();
+        }
+
+        self.frame += 1;
+        true
+    }
+
+    fn is_key_pressed(&self, key_num: u8) -> bool {
+        self.keys_pressed[(key_num & 0xF) as usize]
+    }
+
+    fn set_key(&mut self, key_num: u8, pressed: bool) {
+        self.keys_pressed[(key_num & 0xF) as usize] = pressed;
+    }
+}
+
+impl AudioBackend for HeadlessIO {
+    fn play_audio(&self) {
+        if !self.audio_playing.get() {
+            self.sound_starts
+                .borrow_mut()
+                .push(self.frame.saturating_sub(1));
+        }
+        self.audio_playing.set(true);
+        self.sound_frames.set(self.sound_frames.get() + 1);
+    }
+
+    fn pause_audio(&self) {
+        self.audio_playing.set(false);
+    }
+
+    fn set_audio_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], pitch: u8) {
+        self.audio_pattern = Some((*pattern, pitch));
+    }
+}
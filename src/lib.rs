@@ -0,0 +1,47 @@
+//! A CHIP-8 interpreter that can be embedded in other programs.
+//!
+//! The machine itself is [`Chip8`]. It owns its memory and a monochrome
+//! [`Framebuffer`], and talks to the outside world through a [`Backend`],
+//! which presents frames, provides the hex keypad and plays a tone.
+//! [`HeadlessIO`] keeps everything in memory and works anywhere, while
+//! `Chip8IO` (behind the default `sdl` feature) opens an SDL2 window.
+//!
+//! A typical embedding creates a backend wrapped in `Rc<RefCell<_>>`, builds a
+//! machine with [`Chip8::new`], loads a program with [`Chip8::load_rom_bytes`]
+//! and then calls [`Chip8::run_frame`] sixty times per second, or
+//! [`Chip8::run_cycle`] to execute one instruction at a time. Registers,
+//! memory and the screen can be inspected through the getters on [`Chip8`],
+//! and keys are injected with [`Chip8::set_key`].
+
+pub mod analysis;
+pub mod asm;
+pub mod backend;
+pub mod chip8;
+#[cfg(feature = "sdl")]
+pub mod chip8_io;
+pub mod color;
+pub mod debugger;
+pub mod disasm;
+pub mod filters;
+pub mod frame_blend;
+pub mod framebuffer;
+pub mod gdbstub;
+pub mod headless;
+pub mod machine_code;
+pub mod palette;
+pub mod phosphor;
+pub mod quirks;
+pub mod rewind;
+pub mod savestate;
+pub mod smc;
+pub mod trace;
+pub mod upscale;
+pub mod watch;
+
+pub use backend::{AudioBackend, Backend, DisplayBackend, Hotkey, InputBackend};
+pub use chip8::{Chip8, Chip8Error, Opcode};
+#[cfg(feature = "sdl")]
+pub use chip8_io::Chip8IO;
+pub use framebuffer::Framebuffer;
+pub use headless::HeadlessIO;
+pub use quirks::{Quirks, QuirksPreset};
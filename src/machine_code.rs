@@ -0,0 +1,42 @@
+/// What `0NNN`, a call to COSMAC VIP machine code at NNN, does. `00E0`, `00EE`
+/// and the SUPER-CHIP/XO-CHIP `00XX` instructions are not affected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MachineCodePolicy {
+    /// Stop with `Chip8Error::InvalidOpcode`.
+    Error,
+    /// Skip the call.
+    Ignore,
+}
+
+impl std::str::FromStr for MachineCodePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(MachineCodePolicy::Error),
+            "ignore" => Ok(MachineCodePolicy::Ignore),
+            _ => Err(format!(
+                "Unknown machine code policy '{}': expected error or ignore",
+                s
+            )),
+        }
+    }
+}
+
+/// A machine code call skipped under `MachineCodePolicy::Ignore`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkippedCall {
+    pub address: u16,
+    /// The address of the `0NNN` instruction.
+    pub pc: usize,
+}
+
+impl std::fmt::Display for SkippedCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Ignoring call to machine code at {:#05X} from {:#05X}",
+            self.address, self.pc
+        )
+    }
+}
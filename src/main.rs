@@ -1,49 +1,37 @@
-mod chip8;
-mod chip8_io;
-
-use chip8::*;
-use chip8_io::*;
-use clap::Parser;
+#[cfg(feature = "sdl")]
+use chip8_emulator_rust::Chip8IO;
+use chip8_emulator_rust::chip8::OutOfBoundsPolicy;
+use chip8_emulator_rust::debugger::Debugger;
+use chip8_emulator_rust::filters::Filter;
+use chip8_emulator_rust::frame_blend::FrameBlendMode;
+use chip8_emulator_rust::gdbstub::GdbStub;
+use chip8_emulator_rust::headless::{HeadlessIO, parse_key_script};
+use chip8_emulator_rust::machine_code::MachineCodePolicy;
+use chip8_emulator_rust::palette::{Palette, parse_hex_color};
+use chip8_emulator_rust::quirks::{LoadStoreQuirk, Quirks, QuirksPreset};
+use chip8_emulator_rust::rewind::RewindBuffer;
+use chip8_emulator_rust::smc::SmcAction;
+use chip8_emulator_rust::trace::Tracer;
+use chip8_emulator_rust::upscale::Upscaler;
+use chip8_emulator_rust::watch::Watchpoint;
+use chip8_emulator_rust::{Backend, Chip8, Hotkey, chip8};
+use chip8_emulator_rust::{analysis, asm, disasm};
+use clap::{Parser, Subcommand};
+use std::{cell::RefCell, rc::Rc};
 
 const FRAME_RATE: u64 = 60;
 const FRAME_TIME_MICROSECONDS: u64 = 1000000 / FRAME_RATE;
-const FONT_SIZE: usize = 80;
-const FONT: [u8; FONT_SIZE] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-];
-
-/// Custom parser for hex color strings.
-/// This function accepts strings like "0xFF0000FF" and parses them into a u32.
-fn parse_hex_color(s: &str) -> Result<u32, String> {
-    let s = s.trim();
-    let s = if s.starts_with("0x") || s.starts_with("0X") {
-        &s[2..]
-    } else {
-        s
-    };
-    u32::from_str_radix(s, 16).map_err(|e| format!("Invalid hex color '{}': {}", s, e))
-}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Chip8 emulator in Rust", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to a ROM
-    path_to_rom: std::path::PathBuf,
+    #[arg(required = true)]
+    path_to_rom: Option<std::path::PathBuf>,
 
     /// Scale factor for the original 64 x 32 screen size
     #[arg(long, default_value_t = 24)]
@@ -62,43 +50,557 @@ struct Args {
     /// Accepts hex values like "0x000000FF".
     #[arg(long, default_value = "0x000000FF", value_parser = parse_hex_color)]
     secondary_color: u32,
+
+    /// Color of pixels lit only in the second XO-CHIP plane, in rgba format
+    #[arg(long, default_value = "0xAAAAAAFF", value_parser = parse_hex_color)]
+    plane2_color: u32,
+
+    /// Color of pixels lit in both XO-CHIP planes, in rgba format
+    #[arg(long, default_value = "0x555555FF", value_parser = parse_hex_color)]
+    blend_color: u32,
+
+    /// Palette preset (classic, amber, green, lcd or octo) or palette file, replacing the color options.
+    /// F10 cycles through the presets at runtime
+    #[arg(long, value_parser = Palette::from_name_or_file)]
+    palette: Option<Palette>,
+
+    /// Fade cleared pixels out over this many frames to reduce flicker, toggled at runtime with F9
+    #[arg(long)]
+    phosphor_fade: Option<u32>,
+
+    /// Show the OR of the last N frames ("or" or "or:N"), or hold frames while sprites are being erased ("settle")
+    #[arg(long)]
+    frame_blend: Option<FrameBlendMode>,
+
+    /// Upscaler run on the CPU: nearest, scale2x, epx, scale3x or xbr
+    #[arg(long, default_value = "nearest")]
+    upscaler: Upscaler,
+
+    /// Display filter computed on the CPU: scanlines, grid, bloom or rounded (repeatable, applied in order)
+    #[arg(long)]
+    filter: Vec<Filter>,
+
+    /// Quirks preset: modern, vip, chip48, schip or xochip
+    #[arg(long, default_value = "modern")]
+    quirks: QuirksPreset,
+
+    /// Override the preset: 8XY6/8XYE shift VY instead of VX
+    #[arg(long)]
+    quirk_shift: Option<bool>,
+
+    /// Override the preset: how FX55/FX65 change I (unchanged, x or x+1)
+    #[arg(long)]
+    quirk_load_store: Option<LoadStoreQuirk>,
+
+    /// Override the preset: BNNN jumps to NNN + VX instead of NNN + V0
+    #[arg(long)]
+    quirk_jump: Option<bool>,
+
+    /// Override the preset: 8XY1/8XY2/8XY3 reset VF
+    #[arg(long)]
+    quirk_vf_reset: Option<bool>,
+
+    /// Override the preset: clip sprites at the screen edges instead of wrapping
+    #[arg(long)]
+    quirk_clip: Option<bool>,
+
+    /// Override the preset: DXYN draws at most one sprite per frame
+    #[arg(long)]
+    quirk_display_wait: Option<bool>,
+
+    /// Override the preset: I is 16 bits wide instead of 12, as XO-CHIP needs for its 64KB of memory
+    #[arg(long)]
+    quirk_wide_i: Option<bool>,
+
+    /// Override the preset: how many nested 2NNN calls fit on the stack
+    #[arg(long)]
+    stack_depth: Option<usize>,
+
+    /// What 0NNN machine code calls do: error or ignore
+    #[arg(long, default_value = "error")]
+    machine_code: MachineCodePolicy,
+
+    /// What memory accesses past the end of memory do: error or wrap
+    #[arg(long, default_value = "error")]
+    out_of_bounds: OutOfBoundsPolicy,
+
+    /// Load a save state file before starting
+    #[arg(long)]
+    load_state: Option<std::path::PathBuf>,
+
+    /// Memory budget in megabytes for rewind snapshots, 0 disables rewinding
+    #[arg(long, default_value_t = 32)]
+    rewind_budget: usize,
+
+    /// Start paused in the interactive terminal debugger
+    #[arg(long)]
+    debug: bool,
+
+    /// Watch memory, a register or I, like "300-30F w log", "V3" or "I" (repeatable)
+    #[arg(long)]
+    watch: Vec<Watchpoint>,
+
+    /// Write a line per executed instruction with the registers and timers to this file
+    #[arg(long)]
+    trace: Option<std::path::PathBuf>,
+
+    /// Wait for a gdb remote protocol client on this localhost TCP port before starting
+    #[arg(long)]
+    gdb: Option<u16>,
+
+    /// Warn or break in the debugger when code is overwritten after it ran or runs after being modified
+    #[arg(long)]
+    detect_smc: Option<SmcAction>,
+
+    /// Run without a window or audio device
+    #[arg(long)]
+    headless: bool,
+
+    /// Stop after this many frames (headless mode only)
+    #[arg(long)]
+    frames: Option<u64>,
+
+    /// File with scripted key events like "10:5:down, 20:5:up" (headless mode only)
+    #[arg(long)]
+    key_script: Option<std::path::PathBuf>,
 }
 
-fn main() {
-    let args = Args::parse();
-    let chip8_io = std::rc::Rc::new(std::cell::RefCell::new(Chip8IO::new(
-        args.scale_factor,
-        args.primary_color,
-        args.secondary_color,
-    )));
-    let mut chip8_cpu = Chip8::new(&chip8_io);
-    let mut rom_file = std::fs::File::open(args.path_to_rom).expect("Failed to open ROM file");
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print a disassembly listing of a ROM
+    Disasm {
+        /// Path to a ROM
+        path_to_rom: std::path::PathBuf,
+
+        /// Print only the instructions, in the syntax the asm command reads
+        #[arg(long)]
+        source: bool,
+    },
+    /// Assemble a source file into a ROM
+    Asm {
+        /// Path to the assembly source
+        source: std::path::PathBuf,
+
+        /// Where to write the ROM, defaults to the source path with a .ch8 extension
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Find the reachable code, subroutines and probable data of a ROM without running it
+    Analyze {
+        /// Path to a ROM
+        path_to_rom: std::path::PathBuf,
+
+        /// Also write the control flow graph in Graphviz DOT format to this file
+        #[arg(long)]
+        dot: Option<std::path::PathBuf>,
+    },
+}
+
+impl Args {
+    /// The ROM to run. Clap requires it whenever no subcommand is given.
+    fn rom_path(&self) -> &std::path::Path {
+        self.path_to_rom
+            .as_deref()
+            .expect("a ROM path is required without a subcommand")
+    }
+
+    /// The palettes F10 cycles through, starting with `--palette` or the one the color options describe.
+    #[cfg(feature = "sdl")]
+    fn palettes(&self) -> Vec<Palette> {
+        let first = self.palette.clone().unwrap_or_else(|| {
+            let colors = vec![
+                self.secondary_color,
+                self.primary_color,
+                self.plane2_color,
+                self.blend_color,
+            ];
+            Palette::presets()
+                .into_iter()
+                .find(|preset| preset.colors() == colors)
+                .unwrap_or_else(|| Palette::new("custom", colors).expect("four colors"))
+        });
+        let others: Vec<Palette> = Palette::presets()
+            .into_iter()
+            .filter(|preset| preset.colors() != first.colors())
+            .collect();
+        std::iter::once(first).chain(others).collect()
+    }
+
+    /// The quirks preset with any individual overrides applied.
+    fn quirks(&self) -> Quirks {
+        let mut quirks = Quirks::from_preset(self.quirks);
+        if let Some(shift_uses_vy) = self.quirk_shift {
+            quirks.shift_uses_vy = shift_uses_vy;
+        }
+        if let Some(load_store) = self.quirk_load_store {
+            quirks.load_store = load_store;
+        }
+        if let Some(jump_uses_vx) = self.quirk_jump {
+            quirks.jump_uses_vx = jump_uses_vx;
+        }
+        if let Some(vf_reset) = self.quirk_vf_reset {
+            quirks.vf_reset = vf_reset;
+        }
+        if let Some(clip_sprites) = self.quirk_clip {
+            quirks.clip_sprites = clip_sprites;
+        }
+        if let Some(display_wait) = self.quirk_display_wait {
+            quirks.display_wait = display_wait;
+        }
+        if let Some(wide_index) = self.quirk_wide_i {
+            quirks.wide_index = wide_index;
+        }
+        if let Some(stack_depth) = self.stack_depth {
+            quirks.stack_depth = stack_depth;
+        }
+        quirks
+    }
+}
+
+/// The file for save state `slot`, next to the ROM: `pong.ch8` uses `pong.state1` for slot 1.
+fn state_slot_path(path_to_rom: &std::path::Path, slot: u8) -> std::path::PathBuf {
+    path_to_rom.with_extension(format!("state{}", slot))
+}
+
+fn load_state_file(chip8_cpu: &mut Chip8, path: &std::path::Path) -> Result<(), String> {
+    let state =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    chip8_cpu
+        .load_state(&state)
+        .map_err(|e| format!("Failed to load {}: {}", path.display(), e))
+}
+
+fn handle_hotkey(args: &Args, chip8_cpu: &mut Chip8, hotkey: Hotkey) {
+    match hotkey {
+        Hotkey::SaveState(slot) => {
+            let path = state_slot_path(args.rom_path(), slot);
+            match std::fs::write(&path, chip8_cpu.save_state()) {
+                Ok(()) => println!("Saved state to {}", path.display()),
+                Err(e) => println!("Failed to write {}: {}", path.display(), e),
+            }
+        }
+        Hotkey::Rewind => {}
+        Hotkey::LoadState(slot) => {
+            let path = state_slot_path(args.rom_path(), slot);
+            match load_state_file(chip8_cpu, &path) {
+                Ok(()) => println!("Loaded state from {}", path.display()),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+}
+
+type TraceFile = Tracer<std::io::BufWriter<std::fs::File>>;
+
+/// Prints the first self-modifying code event at each address, pausing the debugger on it for `SmcAction::Break`.
+fn report_code_events(chip8_cpu: &mut Chip8, action: SmcAction, debugger: &mut Option<Debugger>) {
+    for event in chip8_cpu.take_code_events() {
+        if !event.first {
+            continue;
+        }
+        println!("{}", event);
+        if action == SmcAction::Break
+            && let Some(debugger) = debugger
+        {
+            debugger.pause();
+        }
+    }
+}
+
+/// Runs one frame like `Chip8::run_frame`, through the gdb stub, debugger and tracer when they are enabled.
+fn run_frame(
+    chip8_cpu: &mut Chip8,
+    instructions: u32,
+    gdb: &mut Option<GdbStub>,
+    debugger: &mut Option<Debugger>,
+    tracer: &mut Option<TraceFile>,
+    smc: Option<SmcAction>,
+) -> chip8::Result<()> {
+    chip8_cpu.update_timers();
+    for _ in 0..instructions {
+        if let Some(gdb) = gdb
+            && !gdb.before_cycle(chip8_cpu)
+        {
+            return Ok(());
+        }
+        if let Some(debugger) = debugger
+            && !debugger.before_cycle(chip8_cpu)
+        {
+            return Ok(());
+        }
+        if let Some(trace) = tracer
+            && let Err(e) = trace.trace(chip8_cpu)
+        {
+            println!("Failed to write trace, tracing stopped: {}", e);
+            *tracer = None;
+        }
+        let result = match debugger {
+            Some(debugger) => debugger.run_cycle(chip8_cpu),
+            None => chip8_cpu.run_cycle(),
+        };
+        if let Some(gdb) = gdb {
+            gdb.after_cycle(chip8_cpu, &result);
+        }
+        if let Some(action) = smc {
+            report_code_events(chip8_cpu, action, debugger);
+        }
+        for call in chip8_cpu.take_skipped_calls() {
+            println!("{}", call);
+        }
+        result?;
+    }
+    Ok(())
+}
 
-    chip8_cpu.load_rom(&mut rom_file);
-    chip8_cpu.load_font(&FONT[..], FONT_SIZE);
+/// Runs the ROM until the backend asks to quit or an instruction fails.
+/// Frames are paced to `FRAME_RATE` only when `realtime` is set.
+fn run<B: Backend + 'static>(args: &Args, chip8_io: &Rc<RefCell<B>>, realtime: bool) {
+    let mut chip8_cpu = Chip8::new(chip8_io);
+    chip8_cpu.set_quirks(args.quirks());
+    chip8_cpu.set_machine_code_policy(args.machine_code);
+    chip8_cpu.set_out_of_bounds_policy(args.out_of_bounds);
+    chip8_cpu.set_track_code(args.detect_smc.is_some());
+    let mut rom_file = match std::fs::File::open(args.rom_path()) {
+        Ok(rom_file) => rom_file,
+        Err(e) => {
+            println!("Failed to open {}: {}", args.rom_path().display(), e);
+            return;
+        }
+    };
+
+    if let Err(e) = chip8_cpu.load_rom(&mut rom_file) {
+        println!("{}", e);
+        return;
+    }
+
+    if let Some(path) = &args.load_state
+        && let Err(e) = load_state_file(&mut chip8_cpu, path)
+    {
+        println!("{}", e);
+        return;
+    }
 
     let target_frame_duration = std::time::Duration::from_micros(FRAME_TIME_MICROSECONDS);
+    let mut rewind_buffer = RewindBuffer::new(args.rewind_budget * 1024 * 1024);
+    let mut rewound_last_frame = false;
+    // Watchpoints and breaking on self-modifying code run through the debugger, which only starts paused with --debug.
+    let needs_debugger =
+        args.debug || !args.watch.is_empty() || args.detect_smc == Some(SmcAction::Break);
+    let mut debugger = needs_debugger.then(|| {
+        let mut debugger = Debugger::new();
+        if !args.debug {
+            debugger.resume();
+        }
+        for watchpoint in &args.watch {
+            debugger.add_watchpoint(*watchpoint);
+        }
+        debugger
+    });
+    let mut tracer = match &args.trace {
+        Some(path) => match std::fs::File::create(path) {
+            Ok(file) => Some(Tracer::new(std::io::BufWriter::new(file))),
+            Err(e) => {
+                println!("Failed to create {}: {}", path.display(), e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let mut gdb = match args.gdb {
+        Some(port) => match GdbStub::listen(port) {
+            Ok(gdb) => Some(gdb),
+            Err(e) => {
+                println!("Failed to start the gdb stub on port {}: {}", port, e);
+                return;
+            }
+        },
+        None => None,
+    };
 
-    while chip8_io.borrow_mut().poll_input() {
+    while !chip8_cpu.has_exited() && chip8_io.borrow_mut().poll_input() {
         let frame_start = std::time::Instant::now();
 
-        chip8_cpu.update_timers();
+        let hotkeys = chip8_io.borrow_mut().take_hotkeys();
+        let rewinding = hotkeys.contains(&Hotkey::Rewind);
+        for hotkey in hotkeys {
+            handle_hotkey(args, &mut chip8_cpu, hotkey);
+        }
+        let was_rewinding = std::mem::replace(&mut rewound_last_frame, rewinding);
 
-        for _ in 0..args.instructions_per_second {
-            if let Err(e) = chip8_cpu.run_cycle() {
+        if rewinding {
+            // Step back one frame per frame held, stopping at the oldest snapshot.
+            chip8_io.borrow().pause_audio();
+            if !was_rewinding {
+                // The newest snapshot is the frame already on screen.
+                rewind_buffer.pop();
+            }
+            if let Some(state) = rewind_buffer.pop()
+                && let Err(e) = chip8_cpu.load_state(&state)
+            {
+                println!("Skipped a rewind snapshot: {}", e);
+            }
+        } else {
+            let result = run_frame(
+                &mut chip8_cpu,
+                args.instructions_per_second,
+                &mut gdb,
+                &mut debugger,
+                &mut tracer,
+                args.detect_smc,
+            );
+            if let Err(e) = result {
+                match &mut debugger {
+                    Some(debugger) => debugger.post_mortem(&chip8_cpu, &e),
+                    None => println!("{}", e),
+                }
+                break;
+            }
+            if debugger.as_ref().is_some_and(Debugger::has_quit)
+                || gdb.as_ref().is_some_and(GdbStub::has_quit)
+            {
+                break;
+            }
+            if args.rewind_budget > 0 {
+                rewind_buffer.push(chip8_cpu.save_state());
+            }
+        }
+
+        if realtime {
+            let frame_end = std::time::Instant::now();
+            let time_elapsed = frame_end - frame_start;
+            let sleep_time = target_frame_duration.saturating_sub(time_elapsed);
+
+            if sleep_time.as_micros() > 0u128 {
+                std::thread::sleep(sleep_time);
+            }
+        }
+
+        chip8_io.borrow_mut().render_frame(chip8_cpu.framebuffer());
+    }
+
+    if let Some(tracker) = chip8_cpu.code_tracker() {
+        println!("{}", tracker.summary());
+    }
+}
+
+fn run_headless(args: &Args) {
+    let mut headless_io = HeadlessIO::new();
+    headless_io.set_max_frames(args.frames);
+
+    if let Some(path) = &args.key_script {
+        let script = match std::fs::read_to_string(path) {
+            Ok(script) => script,
+            Err(e) => {
+                println!("Failed to read {}: {}", path.display(), e);
+                return;
+            }
+        };
+        match parse_key_script(&script) {
+            Ok(events) => headless_io.set_key_script(events),
+            Err(e) => {
                 println!("{}", e);
                 return;
             }
         }
+    }
+
+    let chip8_io = Rc::new(RefCell::new(headless_io));
+    run(args, &chip8_io, false);
+
+    let headless_io = chip8_io.borrow();
+    print!("{}", headless_io.last_frame());
+    println!(
+        "frames: {}, sound frames: {}, sound starts: {:?}",
+        headless_io.frame(),
+        headless_io.sound_frames(),
+        headless_io.sound_starts()
+    );
+}
+
+#[cfg(feature = "sdl")]
+fn run_sdl(args: &Args) {
+    let mut chip8_io = Chip8IO::new(args.scale_factor, args.palettes());
+    chip8_io.set_phosphor_fade(args.phosphor_fade);
+    chip8_io.set_frame_blend(args.frame_blend);
+    chip8_io.set_upscaler(args.upscaler);
+    chip8_io.set_filters(args.filter.clone());
+    let chip8_io = Rc::new(RefCell::new(chip8_io));
+    run(args, &chip8_io, true);
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_sdl(_args: &Args) {
+    println!("This build has no SDL support, run with --headless");
+}
+
+fn print_disassembly(path_to_rom: &std::path::Path, source: bool) {
+    let rom = match std::fs::read(path_to_rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("Failed to read {}: {}", path_to_rom.display(), e);
+            return;
+        }
+    };
+    for instruction in disasm::disassemble_rom(&rom) {
+        if source {
+            println!("{}", instruction.text);
+        } else {
+            println!("{}", disasm::format_line(&instruction));
+        }
+    }
+}
 
-        let frame_end = std::time::Instant::now();
-        let time_elapsed = frame_end - frame_start;
-        let sleep_time = target_frame_duration - time_elapsed;
+fn analyze_rom(path_to_rom: &std::path::Path, dot: Option<&std::path::Path>) {
+    let rom = match std::fs::read(path_to_rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("Failed to read {}: {}", path_to_rom.display(), e);
+            return;
+        }
+    };
+    let analysis = analysis::analyze(&rom);
+    print!("{}", analysis.listing());
+    if let Some(dot) = dot
+        && let Err(e) = std::fs::write(dot, analysis.to_dot())
+    {
+        println!("Failed to write {}: {}", dot.display(), e);
+    }
+}
 
-        if sleep_time.as_micros() > 0u128 {
-            std::thread::sleep(sleep_time);
+fn assemble_file(source_path: &std::path::Path, output: Option<&std::path::Path>) {
+    let source = match std::fs::read_to_string(source_path) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Failed to read {}: {}", source_path.display(), e);
+            return;
+        }
+    };
+    let rom = match asm::assemble(&source) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("{}: {}", source_path.display(), e);
+            return;
         }
+    };
+    let output = output
+        .map(std::path::Path::to_path_buf)
+        .unwrap_or_else(|| source_path.with_extension("ch8"));
+    match std::fs::write(&output, &rom) {
+        Ok(()) => println!("Wrote {} bytes to {}", rom.len(), output.display()),
+        Err(e) => println!("Failed to write {}: {}", output.display(), e),
+    }
+}
 
-        chip8_io.borrow_mut().render_frame();
+fn main() {
+    let args = Args::parse();
+    match &args.command {
+        Some(Command::Disasm {
+            path_to_rom,
+            source,
+        }) => print_disassembly(path_to_rom, *source),
+        Some(Command::Asm { source, output }) => assemble_file(source, output.as_deref()),
+        Some(Command::Analyze { path_to_rom, dot }) => analyze_rom(path_to_rom, dot.as_deref()),
+        None if args.headless => run_headless(&args),
+        None => run_sdl(&args),
     }
 }
@@ -0,0 +1,135 @@
+//! Display palettes: built-in presets and palette files.
+//!
+//! A palette maps framebuffer color indices to colors. Entry 0 is the
+//! background, entry 1 plane 1, entry 2 plane 2 and entry 3 both planes.
+//! Palettes have 4 or 16 entries, the latter for programs using more planes.
+//!
+//! A palette file lists one color per line in the format `--primary-color`
+//! takes, like `0xFFCC00FF`. Blank lines and lines starting with `;` are ignored:
+//!
+//! ```text
+//! ; Octo's defaults
+//! 0x996600FF
+//! 0xFFCC00FF
+//! 0xFF6600FF
+//! 0x662200FF
+//! ```
+
+use std::path::Path;
+
+/// The entry counts a palette may have.
+pub const PALETTE_SIZES: [usize; 2] = [4, 16];
+
+/// The built-in palettes by name, background first.
+pub const PRESETS: [(&str, [u32; 4]); 5] = [
+    ("classic", [0x000000FF, 0xFFFFFFFF, 0xAAAAAAFF, 0x555555FF]),
+    ("amber", [0x1A0F00FF, 0xFFB000FF, 0xB37B00FF, 0x664600FF]),
+    ("green", [0x001A00FF, 0x33FF33FF, 0x22AA22FF, 0x115511FF]),
+    ("lcd", [0x9BBC0FFF, 0x0F380FFF, 0x306230FF, 0x8BAC0FFF]),
+    ("octo", [0x996600FF, 0xFFCC00FF, 0xFF6600FF, 0x662200FF]),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    colors: Vec<u32>,
+}
+
+/// Parses a color like "0xFF0000FF" into a u32.
+pub fn parse_hex_color(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    let s = if s.starts_with("0x") || s.starts_with("0X") {
+        &s[2..]
+    } else {
+        s
+    };
+    u32::from_str_radix(s, 16).map_err(|e| format!("Invalid hex color '{}': {}", s, e))
+}
+
+impl Palette {
+    /// Fails unless there are 4 or 16 colors.
+    pub fn new(name: &str, colors: Vec<u32>) -> Result<Self, String> {
+        if !PALETTE_SIZES.contains(&colors.len()) {
+            return Err(format!(
+                "Palette '{}' has {} colors, expected 4 or 16",
+                name,
+                colors.len()
+            ));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            colors,
+        })
+    }
+
+    /// The built-in palette called `name`, ignoring case.
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|(preset, colors)| Self {
+                name: preset.to_string(),
+                colors: colors.to_vec(),
+            })
+    }
+
+    /// All built-in palettes, in the order of `PRESETS`.
+    pub fn presets() -> Vec<Self> {
+        PRESETS
+            .iter()
+            .filter_map(|(name, _)| Self::preset(name))
+            .collect()
+    }
+
+    /// Parses the contents of a palette file.
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let colors = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with(';'))
+            .map(parse_hex_color)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(name, colors)
+    }
+
+    /// Reads a palette file, named after the file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::parse(&name, &text)
+    }
+
+    /// A preset by name, otherwise a palette file at that path.
+    pub fn from_name_or_file(s: &str) -> Result<Self, String> {
+        if let Some(palette) = Self::preset(s) {
+            return Ok(palette);
+        }
+        let path = Path::new(s);
+        if !path.exists() {
+            let names: Vec<&str> = PRESETS.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "Unknown palette '{}': expected {} or a palette file",
+                s,
+                names.join(", ")
+            ));
+        }
+        Self::load(path)
+    }
+
+    /// The color for a framebuffer color index. Indices past the end wrap around.
+    pub fn color(&self, index: u8) -> u32 {
+        self.colors[index as usize % self.colors.len()]
+    }
+
+    pub fn colors(&self) -> &[u32] {
+        &self.colors
+    }
+
+    pub fn background(&self) -> u32 {
+        self.colors[0]
+    }
+}
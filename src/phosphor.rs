@@ -0,0 +1,61 @@
+//! Phosphor persistence: lit pixels fade out instead of switching off at once.
+//!
+//! CHIP-8 programs move sprites by erasing and redrawing them with XOR, so
+//! many presented frames are half drawn and sprites flicker. `PhosphorFade`
+//! keeps a pixel that was switched off showing its last color, decaying toward
+//! the background over a number of frames, the way a CRT's phosphor glows on.
+//! It only changes the colors shown, never the machine's framebuffer.
+
+use crate::color;
+
+/// The fade length used when fading is switched on at runtime without one being configured.
+pub const DEFAULT_FADE_FRAMES: u32 = 6;
+
+pub struct PhosphorFade {
+    frames: u32,
+    /// Frames left until each pixel has faded to the background.
+    remaining: Vec<u32>,
+    /// The color each pixel had when it was last lit.
+    last_colors: Vec<u32>,
+}
+
+impl PhosphorFade {
+    /// A cleared pixel fades to the background over `frames` frames.
+    pub fn new(frames: u32) -> Self {
+        Self {
+            frames: frames.max(1),
+            remaining: Vec::new(),
+            last_colors: Vec::new(),
+        }
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Replaces each `background` pixel that was lit in the last `frames` frames
+    /// with its old color, faded toward the background by how long ago it was lit.
+    /// Call it once per presented frame with the frame's colors.
+    pub fn apply(&mut self, pixels: &mut [u32], background: u32) {
+        // A resolution change redraws the whole screen, there is nothing to fade from.
+        if self.remaining.len() != pixels.len() {
+            self.remaining = vec![0; pixels.len()];
+            self.last_colors = vec![background; pixels.len()];
+        }
+
+        for ((pixel, remaining), last_color) in pixels
+            .iter_mut()
+            .zip(self.remaining.iter_mut())
+            .zip(self.last_colors.iter_mut())
+        {
+            if *pixel != background {
+                *remaining = self.frames;
+                *last_color = *pixel;
+            } else if *remaining > 0 {
+                *remaining -= 1;
+                let faded = (self.frames - *remaining) * 255 / (self.frames + 1);
+                *pixel = color::mix(*last_color, background, faded as u8);
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+/// How `FX55` and `FX65` leave I after copying V0 through VX.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadStoreQuirk {
+    /// I is not changed (SUPER-CHIP).
+    Unchanged,
+    /// I is advanced by X (CHIP-48).
+    IncrementByX,
+    /// I is advanced by X + 1, pointing past the last copied byte (COSMAC VIP).
+    IncrementByXPlusOne,
+}
+
+impl std::str::FromStr for LoadStoreQuirk {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unchanged" => Ok(LoadStoreQuirk::Unchanged),
+            "x" => Ok(LoadStoreQuirk::IncrementByX),
+            "x+1" => Ok(LoadStoreQuirk::IncrementByXPlusOne),
+            _ => Err(format!(
+                "Unknown load/store quirk '{}': expected unchanged, x or x+1",
+                s
+            )),
+        }
+    }
+}
+
+/// Named sets of quirks matching the interpreters CHIP-8 programs were written for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuirksPreset {
+    /// The behavior this emulator has always had, which suits most modern ROMs.
+    Modern,
+    /// The original COSMAC VIP interpreter.
+    CosmacVip,
+    /// CHIP-48 on the HP-48 calculators.
+    Chip48,
+    /// SUPER-CHIP 1.1.
+    SuperChip,
+    /// XO-CHIP as implemented by Octo.
+    XoChip,
+}
+
+impl std::str::FromStr for QuirksPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "modern" => Ok(QuirksPreset::Modern),
+            "vip" => Ok(QuirksPreset::CosmacVip),
+            "chip48" => Ok(QuirksPreset::Chip48),
+            "schip" => Ok(QuirksPreset::SuperChip),
+            "xochip" => Ok(QuirksPreset::XoChip),
+            _ => Err(format!(
+                "Unknown quirks preset '{}': expected modern, vip, chip48, schip or xochip",
+                s
+            )),
+        }
+    }
+}
+
+/// Behavior of the opcodes that CHIP-8 interpreters historically disagree on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VY and store the result in VX, instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    pub load_store: LoadStoreQuirk,
+    /// `BNNN` jumps to NNN + VX, where X is the high nibble of NNN, instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to 0.
+    pub vf_reset: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around to the other side.
+    pub clip_sprites: bool,
+    /// `DXYN` waits for the start of a frame, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+    /// I is 16 bits wide, to address all of XO-CHIP's memory. Otherwise it is
+    /// 12 bits, and `FX1E` sets VF when I crosses 0x1000.
+    pub wide_index: bool,
+    /// The most return addresses `2NNN` can push before the stack overflows.
+    pub stack_depth: usize,
+}
+
+impl Quirks {
+    pub fn from_preset(preset: QuirksPreset) -> Self {
+        match preset {
+            QuirksPreset::Modern => Quirks {
+                shift_uses_vy: false,
+                load_store: LoadStoreQuirk::Unchanged,
+                jump_uses_vx: false,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+                wide_index: false,
+                stack_depth: 16,
+            },
+            QuirksPreset::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                load_store: LoadStoreQuirk::IncrementByXPlusOne,
+                jump_uses_vx: false,
+                vf_reset: true,
+                clip_sprites: true,
+                display_wait: true,
+                wide_index: false,
+                stack_depth: 12,
+            },
+            QuirksPreset::Chip48 => Quirks {
+                shift_uses_vy: false,
+                load_store: LoadStoreQuirk::IncrementByX,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+                wide_index: false,
+                stack_depth: 16,
+            },
+            QuirksPreset::SuperChip => Quirks {
+                shift_uses_vy: false,
+                load_store: LoadStoreQuirk::Unchanged,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+                wide_index: false,
+                stack_depth: 16,
+            },
+            QuirksPreset::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store: LoadStoreQuirk::IncrementByXPlusOne,
+                jump_uses_vx: false,
+                vf_reset: false,
+                clip_sprites: false,
+                display_wait: false,
+                wide_index: true,
+                stack_depth: 16,
+            },
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::from_preset(QuirksPreset::Modern)
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+
+/// A snapshot stored either whole or as the difference to the next newer snapshot.
+enum Snapshot {
+    Full(Vec<u8>),
+    /// Run-length encoded XOR against the next newer snapshot, which has the same length.
+    Delta(Vec<u8>),
+}
+
+impl Snapshot {
+    fn size(&self) -> usize {
+        match self {
+            Snapshot::Full(bytes) | Snapshot::Delta(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// A bounded history of save states for rewinding, newest last.
+///
+/// Consecutive frames differ in only a few bytes, so only the newest snapshot
+/// is kept whole and every older one is stored as a compressed delta. When the
+/// stored bytes exceed the memory budget the oldest snapshots are dropped.
+pub struct RewindBuffer {
+    snapshots: VecDeque<Snapshot>,
+    budget_bytes: usize,
+    used_bytes: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            budget_bytes,
+            used_bytes: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// The number of bytes currently used by stored snapshots.
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        let delta = match self.snapshots.back() {
+            Some(Snapshot::Full(newest)) if newest.len() == state.len() => {
+                Some((newest.len(), encode_delta(newest, &state)))
+            }
+            _ => None,
+        };
+        if let Some((full_size, delta)) = delta {
+            self.used_bytes = self.used_bytes - full_size + delta.len();
+            *self.snapshots.back_mut().unwrap() = Snapshot::Delta(delta);
+        }
+
+        self.used_bytes += state.len();
+        self.snapshots.push_back(Snapshot::Full(state));
+
+        while self.used_bytes > self.budget_bytes {
+            match self.snapshots.pop_front() {
+                Some(oldest) => self.used_bytes -= oldest.size(),
+                None => break,
+            }
+        }
+    }
+
+    /// Removes and returns the newest snapshot.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = match self.snapshots.pop_back()? {
+            Snapshot::Full(bytes) => bytes,
+            Snapshot::Delta(_) => unreachable!("the newest snapshot is always stored whole"),
+        };
+        self.used_bytes -= newest.len();
+
+        if let Some(Snapshot::Delta(delta)) = self.snapshots.back() {
+            let previous = decode_delta(delta, &newest);
+            self.used_bytes = self.used_bytes - delta.len() + previous.len();
+            *self.snapshots.back_mut().unwrap() = Snapshot::Full(previous);
+        }
+        Some(newest)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.used_bytes = 0;
+    }
+}
+
+/// Encodes `old XOR new` as pairs of (zero run length, literal length) varints,
+/// each followed by the literal bytes.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut pos = 0;
+
+    while pos < old.len() {
+        let zeros = old[pos..]
+            .iter()
+            .zip(&new[pos..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        pos += zeros;
+        let literals = old[pos..]
+            .iter()
+            .zip(&new[pos..])
+            .take_while(|(a, b)| a != b)
+            .count();
+
+        write_varint(&mut encoded, zeros);
+        write_varint(&mut encoded, literals);
+        encoded.extend(
+            old[pos..pos + literals]
+                .iter()
+                .zip(&new[pos..pos + literals])
+                .map(|(a, b)| a ^ b),
+        );
+        pos += literals;
+    }
+    encoded
+}
+
+/// Rebuilds the older snapshot from its delta and the newer snapshot.
+fn decode_delta(delta: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut old = new.to_vec();
+    let mut delta_pos = 0;
+    let mut pos = 0;
+
+    while delta_pos < delta.len() {
+        pos += read_varint(delta, &mut delta_pos);
+        let literals = read_varint(delta, &mut delta_pos);
+        for (byte, xor) in old[pos..pos + literals]
+            .iter_mut()
+            .zip(&delta[delta_pos..delta_pos + literals])
+        {
+            *byte ^= xor;
+        }
+        pos += literals;
+        delta_pos += literals;
+    }
+    old
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Snapshots that differ from each other in scattered bytes, with long runs of equal
+    /// bytes in between so the run lengths need multi-byte varints.
+    fn snapshots(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|n| {
+                let mut state = vec![0xAA; 4096];
+                state[0] = n as u8;
+                state[300 + n] = 0x55;
+                state[1000..1000 + n].fill(n as u8);
+                state[4095] = !(n as u8);
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pop_returns_snapshots_newest_first() {
+        let states = snapshots(10);
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), states.len());
+        assert!(buffer.used_bytes() < states.len() * states[0].len());
+
+        for state in states.iter().rev() {
+            assert_eq!(buffer.pop().as_ref(), Some(state));
+        }
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.used_bytes(), 0);
+    }
+
+    #[test]
+    fn budget_evicts_the_oldest_snapshots() {
+        let states = snapshots(50);
+        // Room for the newest snapshot and a few deltas.
+        let mut buffer = RewindBuffer::new(states[0].len() + 64);
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        let kept = buffer.len();
+        assert!(kept > 1 && kept < states.len());
+        assert!(buffer.used_bytes() <= states[0].len() + 64);
+
+        for state in states.iter().rev().take(kept) {
+            assert_eq!(buffer.pop().as_ref(), Some(state));
+        }
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn snapshots_of_different_sizes_are_stored_whole() {
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        buffer.push(vec![1; 10]);
+        buffer.push(vec![2; 20]);
+        buffer.push(vec![3; 20]);
+        assert_eq!(buffer.pop(), Some(vec![3; 20]));
+        assert_eq!(buffer.pop(), Some(vec![2; 20]));
+        assert_eq!(buffer.pop(), Some(vec![1; 10]));
+    }
+
+    #[test]
+    fn states_at_different_call_depths_are_stored_as_deltas() {
+        use crate::chip8::Chip8;
+        use crate::headless::HeadlessIO;
+        use std::{cell::RefCell, rc::Rc};
+
+        let mut chip8 = Chip8::new(&Rc::new(RefCell::new(HeadlessIO::new())));
+        // CALL 0x202, then RET from there.
+        chip8.load_rom_bytes(&[0x22, 0x02, 0x00, 0xEE]).unwrap();
+        let outside = chip8.save_state();
+        chip8.run_cycle().unwrap();
+        let inside = chip8.save_state();
+        assert_eq!(chip8.stack().len(), 1);
+
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        buffer.push(outside.clone());
+        buffer.push(inside.clone());
+        assert!(matches!(buffer.snapshots[0], Snapshot::Delta(_)));
+        assert!(buffer.used_bytes() < inside.len() + 64);
+        assert_eq!(buffer.pop(), Some(inside));
+        assert_eq!(buffer.pop(), Some(outside.clone()));
+
+        chip8.load_state(&outside).unwrap();
+        assert!(chip8.stack().is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+//! The save state file format.
+//!
+//! A save state is a header followed by the machine state. All integers are
+//! little endian.
+//!
+//! | Field    | Size | Contents                                       |
+//! |----------|------|------------------------------------------------|
+//! | magic    | 4    | `C8SS`                                         |
+//! | version  | 2    | `SAVE_STATE_VERSION`                           |
+//! | ROM hash | 8    | `rom_hash` of the ROM the state was saved from |
+//! | body     | rest | written by `Chip8::save_state`                 |
+//!
+//! A state is only loaded if its version matches exactly and its ROM hash
+//! matches the running ROM.
+
+pub const SAVE_STATE_MAGIC: [u8; 4] = *b"C8SS";
+pub const SAVE_STATE_VERSION: u16 = 2;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    RomMismatch { expected: u64, found: u64 },
+    Truncated,
+    InvalidValue(&'static str),
+}
+
+impl std::fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "Not a save state file"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported save state version {}, expected {}",
+                version, SAVE_STATE_VERSION
+            ),
+            SaveStateError::RomMismatch { expected, found } => write!(
+                f,
+                "Save state belongs to another ROM: hash {:016x}, running ROM has {:016x}",
+                found, expected
+            ),
+            SaveStateError::Truncated => write!(f, "Save state is truncated"),
+            SaveStateError::InvalidValue(field) => {
+                write!(f, "Save state has an invalid {}", field)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+/// 64 bit FNV-1a hash identifying a ROM in save state headers.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Appends fields to a save state.
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new(rom_hash: u64) -> Self {
+        let mut writer = Self { bytes: Vec::new() };
+        writer.write_bytes(&SAVE_STATE_MAGIC);
+        writer.write_u16(SAVE_STATE_VERSION);
+        writer.write_u64(rom_hash);
+        writer
+    }
+
+    pub(crate) fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub(crate) fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_u128(&mut self, value: u128) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads fields back in the order `StateWriter` wrote them.
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the header against the running ROM and returns a reader positioned at the body.
+    pub(crate) fn new(bytes: &'a [u8], rom_hash: u64) -> Result<Self, SaveStateError> {
+        let mut reader = Self { bytes };
+        if reader.read_bytes(SAVE_STATE_MAGIC.len())? != SAVE_STATE_MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = reader.read_u16()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        let found = reader.read_u64()?;
+        if found != rom_hash {
+            return Err(SaveStateError::RomMismatch {
+                expected: rom_hash,
+                found,
+            });
+        }
+        Ok(reader)
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.bytes.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn read_array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub(crate) fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidValue("flag")),
+        }
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub(crate) fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    pub(crate) fn read_u128(&mut self) -> Result<u128, SaveStateError> {
+        Ok(u128::from_le_bytes(self.read_array()?))
+    }
+
+    /// Fails unless every byte has been read.
+    pub(crate) fn finish(self) -> Result<(), SaveStateError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(SaveStateError::InvalidValue("length"))
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Self-modifying code detection.
+//!
+//! `CodeTracker` marks every byte the machine executes and every byte it
+//! writes. It reports an event when an instruction runs from a byte written
+//! since it last ran, and when a byte that already ran as code is written.
+//! Overwrite events are also what a decode cache needs: an entry is stale once
+//! any of its bytes is reported as overwritten.
+
+use crate::chip8::MEMORY_SIZE;
+use std::collections::BTreeSet;
+
+const EXECUTED: u8 = 1 << 0;
+/// Written since it last ran, or since tracking started if it never ran.
+const MODIFIED: u8 = 1 << 1;
+
+/// What to do when self-modifying code is detected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmcAction {
+    Warn,
+    Break,
+}
+
+impl std::str::FromStr for SmcAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "warn" => Ok(SmcAction::Warn),
+            "break" => Ok(SmcAction::Break),
+            _ => Err(format!("Unknown action '{}': expected warn or break", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodeEventKind {
+    /// An instruction ran from a byte written since it last ran.
+    ModifiedExecuted,
+    /// A byte that already ran as code was written.
+    Overwritten,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodeEvent {
+    pub kind: CodeEventKind,
+    pub address: usize,
+    /// The instruction that ran or wrote the byte. None for writes from outside
+    /// the program, like the debugger or gdb.
+    pub pc: Option<usize>,
+    /// Whether this is the first event of its kind at the address.
+    pub first: bool,
+}
+
+impl std::fmt::Display for CodeEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.kind, self.pc) {
+            (CodeEventKind::ModifiedExecuted, Some(pc)) => write!(
+                f,
+                "Instruction at 0x{:03X} runs modified byte 0x{:03X}",
+                pc, self.address
+            ),
+            (CodeEventKind::Overwritten, Some(pc)) => write!(
+                f,
+                "Instruction at 0x{:03X} overwrote code at 0x{:03X}",
+                pc, self.address
+            ),
+            (_, None) => write!(
+                f,
+                "Code at 0x{:03X} was overwritten from outside the program",
+                self.address
+            ),
+        }
+    }
+}
+
+pub struct CodeTracker {
+    flags: Vec<u8>,
+    events: Vec<CodeEvent>,
+    modified_executed: BTreeSet<usize>,
+    overwritten: BTreeSet<usize>,
+    modified_executed_count: u64,
+    overwritten_count: u64,
+}
+
+impl Default for CodeTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodeTracker {
+    pub fn new() -> Self {
+        Self {
+            flags: vec![0; MEMORY_SIZE],
+            events: Vec::new(),
+            modified_executed: BTreeSet::new(),
+            overwritten: BTreeSet::new(),
+            modified_executed_count: 0,
+            overwritten_count: 0,
+        }
+    }
+
+    /// Forgets which bytes ran and were written, for when all of memory is replaced.
+    /// The summary counts are kept.
+    pub fn reset(&mut self) {
+        self.flags.fill(0);
+    }
+
+    /// Marks the `len` bytes of the instruction at `pc` as executed.
+    pub fn record_execution(&mut self, pc: usize, len: usize) {
+        for address in (pc..pc + len).map(|address| address % MEMORY_SIZE) {
+            let flags = &mut self.flags[address];
+            if *flags & MODIFIED != 0 {
+                let first = self.modified_executed.insert(address);
+                self.modified_executed_count += 1;
+                self.events.push(CodeEvent {
+                    kind: CodeEventKind::ModifiedExecuted,
+                    address,
+                    pc: Some(pc),
+                    first,
+                });
+            }
+            *flags = EXECUTED;
+        }
+    }
+
+    /// Marks the byte at `address` as written by the instruction at `pc`.
+    pub fn record_write(&mut self, address: usize, pc: Option<usize>) {
+        let flags = &mut self.flags[address];
+        if *flags & EXECUTED != 0 {
+            let first = self.overwritten.insert(address);
+            self.overwritten_count += 1;
+            self.events.push(CodeEvent {
+                kind: CodeEventKind::Overwritten,
+                address,
+                pc,
+                first,
+            });
+        }
+        *flags |= MODIFIED;
+    }
+
+    pub fn is_executed(&self, address: usize) -> bool {
+        self.flags[address] & EXECUTED != 0
+    }
+
+    /// Whether the byte was written since it last ran.
+    pub fn is_modified(&self, address: usize) -> bool {
+        self.flags[address] & MODIFIED != 0
+    }
+
+    /// The events since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<CodeEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// A report of everything detected since tracking started, for printing at exit.
+    pub fn summary(&self) -> String {
+        if self.modified_executed_count == 0 && self.overwritten_count == 0 {
+            return "No self-modifying code detected".to_string();
+        }
+        format!(
+            "Self-modifying code: {} overwrites of code that already ran ({}), {} executions of modified bytes ({})",
+            self.overwritten_count,
+            format_ranges(&self.overwritten),
+            self.modified_executed_count,
+            format_ranges(&self.modified_executed)
+        )
+    }
+}
+
+/// Formats addresses as comma separated runs, like `0x2A0-0x2A3, 0x300`.
+fn format_ranges(addresses: &BTreeSet<usize>) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &address in addresses {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == address => *end = address,
+            _ => ranges.push((address, address)),
+        }
+    }
+    if ranges.is_empty() {
+        return "none".to_string();
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                format!("0x{:03X}", start)
+            } else {
+                format!("0x{:03X}-0x{:03X}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,71 @@
+//! A per-instruction execution trace.
+//!
+//! Each line describes the machine just before an instruction runs, in fixed
+//! width columns so traces from different runs or emulators can be diffed:
+//!
+//! ```text
+//! CYCLE      PC   OP   DISASSEMBLY          V0 .. VF                                        I    SP DT ST
+//! 0000000005 0208 D015 DRW V0, V1, 5        05 03 0A 00 00 00 00 00 00 00 00 00 00 00 00 00 0082 0  00 00
+//! ```
+//!
+//! The cycle count starts at 0, all values are hex except the cycle count.
+
+use crate::chip8::Chip8;
+use crate::disasm;
+use std::io::Write;
+
+/// Wide enough for the longest mnemonic, `LD I, LONG 0x0000`.
+const DISASSEMBLY_WIDTH: usize = 20;
+
+pub struct Tracer<W: Write> {
+    writer: W,
+    cycle: u64,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, cycle: 0 }
+    }
+
+    /// The number of instructions traced so far.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Writes the line for the instruction at the PC. Call it right before `Chip8::run_cycle`.
+    pub fn trace(&mut self, chip8: &Chip8) -> std::io::Result<()> {
+        let instruction = disasm::disassemble_at(chip8.memory(), chip8.pc());
+        let raw: String = instruction
+            .bytes
+            .iter()
+            .take(2)
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let registers: Vec<String> = chip8
+            .registers()
+            .iter()
+            .map(|value| format!("{:02X}", value))
+            .collect();
+
+        writeln!(
+            self.writer,
+            "{:010} {:04X} {:<4} {:<width$} {} {:04X} {:<2X} {:02X} {:02X}",
+            self.cycle,
+            chip8.pc(),
+            raw,
+            instruction.text,
+            registers.join(" "),
+            chip8.i(),
+            chip8.stack().len(),
+            chip8.delay_timer(),
+            chip8.sound_timer(),
+            width = DISASSEMBLY_WIDTH
+        )?;
+        self.cycle += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,187 @@
+//! Pixel art upscalers, run on the CPU before the frame is uploaded.
+//!
+//! Nearest neighbour is the default and keeps pixels square. The others look
+//! at each pixel's neighbours to round off diagonal edges, then scale the
+//! rest of the way to the window size with nearest neighbour:
+//!
+//! * `scale2x` (also called EPX, its original name) and `scale3x` copy a
+//!   neighbour's color into the corners where two neighbours of the same
+//!   color meet
+//! * `xbr` is a simplified xBR: it detects the same corners at 2x, but blends
+//!   them halfway toward the neighbours' color, so diagonals are antialiased
+
+use crate::color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Upscaler {
+    #[default]
+    Nearest,
+    Scale2x,
+    Scale3x,
+    XbrLite,
+}
+
+impl std::str::FromStr for Upscaler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Upscaler::Nearest),
+            "scale2x" | "epx" => Ok(Upscaler::Scale2x),
+            "scale3x" => Ok(Upscaler::Scale3x),
+            "xbr" | "xbr-lite" => Ok(Upscaler::XbrLite),
+            _ => Err(format!(
+                "Unknown upscaler '{}': expected nearest, scale2x, epx, scale3x or xbr",
+                s
+            )),
+        }
+    }
+}
+
+impl Upscaler {
+    /// The factor the algorithm itself scales by, before nearest neighbour does the rest.
+    fn factor(self) -> usize {
+        match self {
+            Upscaler::Nearest => 1,
+            Upscaler::Scale2x | Upscaler::XbrLite => 2,
+            Upscaler::Scale3x => 3,
+        }
+    }
+}
+
+/// Scales `pixels`, an image `width` pixels wide, by about `scale` into `scaled`.
+/// Returns the factor actually used: the largest multiple of the algorithm's
+/// own factor not above `scale`, or the algorithm's factor if `scale` is smaller.
+pub fn upscale(
+    upscaler: Upscaler,
+    pixels: &[u32],
+    width: usize,
+    scale: usize,
+    scaled: &mut Vec<u32>,
+) -> usize {
+    let factor = upscaler.factor();
+    let rest = (scale / factor).max(1);
+    if factor == 1 {
+        scale_nearest(pixels, width, rest, scaled);
+        return rest;
+    }
+
+    let mut smoothed = vec![0; pixels.len() * factor * factor];
+    let height = pixels.len() / width;
+    let neighbours = Neighbours {
+        pixels,
+        width,
+        height,
+    };
+    for y in 0..height {
+        for x in 0..width {
+            let around = neighbours.around(x, y);
+            let mut write_block = |block: &[u32]| {
+                for (sub_row, sub_pixels) in block.chunks_exact(factor).enumerate() {
+                    let start = (y * factor + sub_row) * width * factor + x * factor;
+                    smoothed[start..start + factor].copy_from_slice(sub_pixels);
+                }
+            };
+            match upscaler {
+                Upscaler::Scale3x => write_block(&scale3x_block(&around)),
+                Upscaler::XbrLite => write_block(&xbr_lite_block(&around)),
+                _ => write_block(&scale2x_block(&around)),
+            }
+        }
+    }
+    scale_nearest(&smoothed, width * factor, rest, scaled);
+    factor * rest
+}
+
+/// Scales `pixels`, an image `width` pixels wide, by `scale` in both directions into `scaled`.
+pub fn scale_nearest(pixels: &[u32], width: usize, scale: usize, scaled: &mut Vec<u32>) {
+    scaled.clear();
+    for row in pixels.chunks_exact(width) {
+        for _ in 0..scale {
+            for &pixel in row {
+                scaled.extend(std::iter::repeat_n(pixel, scale));
+            }
+        }
+    }
+}
+
+struct Neighbours<'a> {
+    pixels: &'a [u32],
+    width: usize,
+    height: usize,
+}
+
+impl Neighbours<'_> {
+    /// The 3x3 block centered on (`x`, `y`) in reading order, repeating the edge pixels at the borders.
+    fn around(&self, x: usize, y: usize) -> [u32; 9] {
+        let mut block = [0; 9];
+        for (i, pixel) in block.iter_mut().enumerate() {
+            let px = (x + i % 3).saturating_sub(1).min(self.width - 1);
+            let py = (y + i / 3).saturating_sub(1).min(self.height - 1);
+            *pixel = self.pixels[py * self.width + px];
+        }
+        block
+    }
+}
+
+/// The 2x2 output for the center of a 3x3 block `[a, b, c, d, e, f, g, h, i]`.
+fn scale2x_block(&[_, b, _, d, e, f, _, h, _]: &[u32; 9]) -> [u32; 4] {
+    if b == h || d == f {
+        return [e; 4];
+    }
+    [
+        if d == b { d } else { e },
+        if b == f { f } else { e },
+        if d == h { d } else { e },
+        if h == f { f } else { e },
+    ]
+}
+
+/// The 3x3 output for the center of a 3x3 block `[a, b, c, d, e, f, g, h, i]`.
+fn scale3x_block(&[a, b, c, d, e, f, g, h, i]: &[u32; 9]) -> [u32; 9] {
+    if b == h || d == f {
+        return [e; 9];
+    }
+    [
+        if d == b { d } else { e },
+        if (d == b && e != c) || (b == f && e != a) {
+            b
+        } else {
+            e
+        },
+        if b == f { f } else { e },
+        if (d == b && e != g) || (d == h && e != a) {
+            d
+        } else {
+            e
+        },
+        e,
+        if (b == f && e != i) || (h == f && e != c) {
+            f
+        } else {
+            e
+        },
+        if d == h { d } else { e },
+        if (d == h && e != i) || (h == f && e != g) {
+            h
+        } else {
+            e
+        },
+        if h == f { f } else { e },
+    ]
+}
+
+/// Like `scale2x_block`, but a corner only takes half of its neighbours' color.
+fn xbr_lite_block(&[_, b, _, d, e, f, _, h, _]: &[u32; 9]) -> [u32; 4] {
+    if b == h || d == f {
+        return [e; 4];
+    }
+    let corner = |side1: u32, side2: u32| {
+        if side1 == side2 && side1 != e {
+            color::mix(e, side1, 128)
+        } else {
+            e
+        }
+    };
+    [corner(d, b), corner(b, f), corner(d, h), corner(h, f)]
+}
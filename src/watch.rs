@@ -0,0 +1,200 @@
+//! Watchpoints on memory ranges, registers and I.
+//!
+//! A watchpoint is written as a target followed by options, for example
+//! `300-30F w log`, `V3` or `I break`:
+//!
+//! * target: a hex address or `START-END` range (inclusive), `V0` to `VF`, or `I`
+//! * for memory targets, `r`, `w` or `rw` (the default) picks the accesses to watch
+//! * `break` (the default) stops in the debugger, `log` only prints the hit
+
+use crate::chip8::{Chip8, MemoryAccess, MemoryAccessKind, NUM_REGISTERS};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchTarget {
+    Memory {
+        start: usize,
+        end: usize,
+        reads: bool,
+        writes: bool,
+    },
+    Register(u8),
+    I,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchAction {
+    Break,
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub target: WatchTarget,
+    pub action: WatchAction,
+}
+
+/// The registers and I before an instruction, to see what it changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterSnapshot {
+    registers: [u8; NUM_REGISTERS],
+    i: usize,
+}
+
+impl RegisterSnapshot {
+    pub fn of(chip8: &Chip8) -> Self {
+        Self {
+            registers: *chip8.registers(),
+            i: chip8.i(),
+        }
+    }
+}
+
+impl Watchpoint {
+    pub fn watches_memory(&self) -> bool {
+        matches!(self.target, WatchTarget::Memory { .. })
+    }
+
+    /// Describes each way the instruction at `pc` triggered the watchpoint, given
+    /// the registers before it ran and the memory accesses it made.
+    pub fn hits(
+        &self,
+        pc: usize,
+        before: &RegisterSnapshot,
+        chip8: &Chip8,
+        accesses: &[MemoryAccess],
+    ) -> Vec<String> {
+        match self.target {
+            WatchTarget::Memory {
+                start,
+                end,
+                reads,
+                writes,
+            } => accesses
+                .iter()
+                .filter(|access| (start..=end).contains(&access.address))
+                .filter(|access| match access.kind {
+                    MemoryAccessKind::Read => reads,
+                    MemoryAccessKind::Write => writes,
+                })
+                .map(|access| {
+                    let kind = match access.kind {
+                        MemoryAccessKind::Read => "read",
+                        MemoryAccessKind::Write => "write",
+                    };
+                    format!(
+                        "{} 0x{:03X} = 0x{:02X} by 0x{:03X}",
+                        kind, access.address, access.value, pc
+                    )
+                })
+                .collect(),
+            WatchTarget::Register(x) => {
+                let (old, new) = (before.registers[x as usize], chip8.registers()[x as usize]);
+                if old == new {
+                    return Vec::new();
+                }
+                vec![format!(
+                    "V{:X} 0x{:02X} -> 0x{:02X} by 0x{:03X}",
+                    x, old, new, pc
+                )]
+            }
+            WatchTarget::I => {
+                if before.i == chip8.i() {
+                    return Vec::new();
+                }
+                vec![format!(
+                    "I 0x{:03X} -> 0x{:03X} by 0x{:03X}",
+                    before.i,
+                    chip8.i(),
+                    pc
+                )]
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let target = words.next().ok_or("Empty watchpoint")?;
+        let mut target = parse_target(target)?;
+        let mut action = WatchAction::Break;
+
+        for word in words {
+            match (word, &mut target) {
+                ("break", _) => action = WatchAction::Break,
+                ("log", _) => action = WatchAction::Log,
+                ("r" | "w" | "rw", WatchTarget::Memory { reads, writes, .. }) => {
+                    *reads = word.contains('r');
+                    *writes = word.contains('w');
+                }
+                _ => {
+                    return Err(format!(
+                        "Unknown watchpoint option '{}': expected r, w or rw for memory, break or log",
+                        word
+                    ));
+                }
+            }
+        }
+        Ok(Watchpoint { target, action })
+    }
+}
+
+impl std::fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.target {
+            WatchTarget::Memory {
+                start,
+                end,
+                reads,
+                writes,
+            } => {
+                write!(f, "{:03X}-{:03X} ", start, end)?;
+                match (reads, writes) {
+                    (true, true) => write!(f, "rw")?,
+                    (true, false) => write!(f, "r")?,
+                    _ => write!(f, "w")?,
+                }
+            }
+            WatchTarget::Register(x) => write!(f, "V{:X}", x)?,
+            WatchTarget::I => write!(f, "I")?,
+        }
+        match self.action {
+            WatchAction::Break => write!(f, " break"),
+            WatchAction::Log => write!(f, " log"),
+        }
+    }
+}
+
+fn parse_target(target: &str) -> Result<WatchTarget, String> {
+    let upper = target.to_ascii_uppercase();
+    if upper == "I" {
+        return Ok(WatchTarget::I);
+    }
+    if upper.len() == 2
+        && let Some(register) = upper.strip_prefix('V')
+        && let Ok(x) = u8::from_str_radix(register, 16)
+    {
+        return Ok(WatchTarget::Register(x));
+    }
+
+    let (start, end) = target.split_once('-').unwrap_or((target, target));
+    let parse = |address: &str| {
+        let hex = address.trim_start_matches("0x").trim_start_matches("0X");
+        usize::from_str_radix(hex, 16).map_err(|_| format!("Invalid watch target '{}'", target))
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start > end {
+        return Err(format!(
+            "Invalid watch range '{}': start is after end",
+            target
+        ));
+    }
+    Ok(WatchTarget::Memory {
+        start,
+        end,
+        reads: true,
+        writes: true,
+    })
+}